@@ -0,0 +1,217 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use clap::Args;
+use sha2::{Digest, Sha256};
+use similar::TextDiff;
+
+use crate::atomic::{self, Pending};
+use crate::collision::Markers;
+use crate::content;
+use crate::format::{self, Format};
+use crate::manifest::{self, FileEntry};
+use crate::preset::{self, LayoutArgs};
+use crate::unbundle::{self, Entry};
+use crate::verify;
+
+/// Arguments for `fbundle apply`.
+#[derive(Debug, Args)]
+pub struct ApplyArgs {
+    /// The edited bundle, or all parts of a split bundle in order
+    #[arg(required = true)]
+    pub bundles: Vec<PathBuf>,
+
+    /// The source directory to write the changed files to
+    #[arg(short = 's', long, default_value = ".")]
+    pub src_dir: PathBuf,
+
+    #[command(flatten)]
+    pub layout: LayoutArgs,
+
+    /// Show the changes without writing them
+    #[arg(long, default_value_t = false)]
+    pub dry_run: bool,
+
+    /// Write files even if they changed since the bundle was created
+    #[arg(long, default_value_t = false)]
+    pub force: bool,
+
+    /// List the files of the original bundle that the edited bundle no longer has
+    #[arg(long, default_value_t = false)]
+    pub report_deleted: bool,
+
+    /// Also list files that are unchanged
+    #[arg(short = 'v', long, default_value_t = false)]
+    pub verbose: bool,
+}
+
+/// A file of the bundle that differs from the source tree.
+struct Change<'a> {
+    entry: &'a Entry,
+    target: PathBuf,
+    /// The file as it is in the source tree, if it exists.
+    current: Option<Vec<u8>>,
+    bytes: Vec<u8>,
+}
+
+impl Change<'_> {
+    /// A unified diff from the source file to the bundle, or a note for binary files.
+    fn preview(&self) -> String {
+        let old = self.current.as_deref().unwrap_or_default();
+        match (std::str::from_utf8(old), std::str::from_utf8(&self.bytes)) {
+            (Ok(old), Ok(new)) if !format::is_binary(&self.entry.encoding) => {
+                let (from, to) = (format!("a/{}", self.entry.path), format!("b/{}", self.entry.path));
+                let from = if self.current.is_some() { from.as_str() } else { "/dev/null" };
+                TextDiff::from_lines(old, new).unified_diff().header(from, &to).to_string()
+            }
+            _ => format!("Binary file {} differs\n", self.entry.path),
+        }
+    }
+
+    /// Why the file cannot be written safely, judged by what the manifest
+    /// recorded when the bundle was created.
+    fn conflict(&self, recorded: Option<&FileEntry>) -> Option<&'static str> {
+        match (recorded, &self.current) {
+            (Some(recorded), Some(current)) if content::to_hex(&Sha256::digest(current)) != recorded.sha256 => {
+                Some("changed since the bundle was created")
+            }
+            (Some(_), None) => Some("deleted since the bundle was created"),
+            (None, Some(_)) => Some("not in the original bundle, but exists"),
+            _ => None,
+        }
+    }
+}
+
+pub fn run(args: ApplyArgs) -> io::Result<()> {
+    let layout = preset::read_back(&args.layout, &args.bundles[0])?;
+    let markers = Markers { file_sep: &layout.file_sep, header: &layout.header, footer: layout.footer.as_ref() };
+
+    let mut entries = Vec::new();
+    for bundle in &args.bundles {
+        entries.extend(unbundle::parse(&fs::read_to_string(bundle)?, &markers, layout.format, args.layout.unescape)?);
+    }
+    let mut entries: Vec<Entry> =
+        unbundle::join_continued(entries).into_iter().filter(|entry| entry.path != manifest::EMBEDDED_PATH).collect();
+
+    let manifest = match verify::load(&args.bundles) {
+        Ok(manifest) => Some(manifest),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            eprintln!("Warning: The bundle has no manifest, so changes made to the source tree since it was created cannot be detected.");
+            None
+        }
+        Err(err) => return Err(err),
+    };
+    let rev = args.layout.git_rev.as_deref().or(manifest.as_ref().and_then(|manifest| manifest.selection.git_rev.as_deref()));
+    unbundle::strip_rev(&mut entries, rev)?;
+    let recorded: HashMap<&str, &FileEntry> = manifest
+        .iter()
+        .flat_map(|manifest| &manifest.files)
+        .filter(|file| file.bundled)
+        .map(|file| (file.path.as_str(), file))
+        .collect();
+
+    let mut changes = Vec::new();
+    let mut unchanged = 0;
+    for entry in &entries {
+        let target = unbundle::target_path(&args.src_dir, &unbundle::safe_relative_path(&entry.path)?, &entry.path)?;
+        let Some(mut bytes) = entry.bytes()? else {
+            if entry.encoding == format::DIFF {
+                eprintln!("Warning: {} is a diff, not the contents of the file. Skipping.", entry.path);
+            }
+            continue;
+        };
+        let current = match fs::read(&target) {
+            Ok(current) => Some(current),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err),
+        };
+        // A Markdown block does not tell whether the file ended in a newline, so keep the ending it has.
+        let unterminated = current.as_ref().is_some_and(|current| !current.is_empty() && !current.ends_with(b"\n"));
+        if layout.format == Format::Markdown && entry.encoding == format::TEXT && unterminated && bytes.ends_with(b"\n") {
+            bytes.pop();
+        }
+        if current.as_ref() == Some(&bytes) {
+            if args.verbose {
+                println!("unchanged  {}", entry.path);
+            }
+            unchanged += 1;
+            continue;
+        }
+        changes.push(Change { entry, target, current, bytes });
+    }
+
+    let mut stdout = io::stdout().lock();
+    for change in &changes {
+        stdout.write_all(change.preview().as_bytes())?;
+    }
+    drop(stdout);
+
+    if args.report_deleted {
+        match &manifest {
+            Some(_) => {
+                let kept: BTreeSet<&str> = entries.iter().map(|entry| entry.path.as_str()).collect();
+                let mut deleted: Vec<&str> = recorded.keys().copied().filter(|path| !kept.contains(path)).collect();
+                deleted.sort_unstable();
+                for path in deleted {
+                    println!("deleted    {} (not in the bundle any more; left in place)", path);
+                }
+            }
+            None => eprintln!("Warning: Without a manifest, files deleted from the bundle cannot be reported."),
+        }
+    }
+
+    let conflicts: Vec<(&str, &str)> = match &manifest {
+        Some(_) => changes
+            .iter()
+            .filter_map(|change| {
+                let reason = change.conflict(recorded.get(change.entry.path.as_str()).copied())?;
+                Some((change.entry.path.as_str(), reason))
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+    for (path, reason) in &conflicts {
+        eprintln!("{}: {} {}", if args.force { "Overwriting" } else { "Conflict" }, path, reason);
+    }
+
+    if args.dry_run {
+        println!(
+            "Would write {} changed files to {} ({} unchanged, {} conflicts)",
+            changes.len(),
+            args.src_dir.display(),
+            unchanged,
+            conflicts.len()
+        );
+        return Ok(());
+    }
+    if !conflicts.is_empty() && !args.force {
+        return Err(io::Error::other(format!(
+            "{} files changed in {} since the bundle was created; nothing was written (use --force to overwrite them)",
+            conflicts.len(),
+            args.src_dir.display()
+        )));
+    }
+
+    atomic::remove_temporaries_on_interrupt()?;
+    // Every file is written under a temporary name first, so an error leaves the source tree untouched.
+    let mut pending = Pending::default();
+    for change in &changes {
+        if let Some(parent) = change.target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let previous = fs::metadata(&change.target).ok();
+        let mut file = pending.create(change.target.clone())?;
+        file.write_all(&change.bytes)?;
+        // Keep the permissions, e.g. the executable bit, of the file being replaced.
+        if let Some(previous) = previous {
+            file.set_permissions(previous.permissions())?;
+        }
+    }
+    pending.commit()?;
+
+    println!("Applied {} changed files to {} ({} unchanged)", changes.len(), args.src_dir.display(), unchanged);
+    Ok(())
+}
+
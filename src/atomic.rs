@@ -0,0 +1,90 @@
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Temporary files of every `Pending` that has not been committed yet, and
+/// other temporary files and directories, so that an interrupt can remove them.
+static TEMPORARIES: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Removes all temporary files and exits when the user presses Ctrl-C.
+pub fn remove_temporaries_on_interrupt() -> io::Result<()> {
+    ctrlc::set_handler(|| {
+        for temporary in lock().iter() {
+            let _ = if temporary.is_dir() { fs::remove_dir_all(temporary) } else { fs::remove_file(temporary) };
+        }
+        std::process::exit(130);
+    })
+    .map_err(io::Error::other)
+}
+
+/// A set of files written under temporary names in their final directory and
+/// moved into place together by `commit`. Until then, the previous versions
+/// stay untouched; if the files are never committed, because of an error or
+/// an interrupt, the temporary files are removed.
+#[derive(Debug, Default)]
+pub struct Pending {
+    /// Temporary files and the paths they are renamed to.
+    files: Vec<(PathBuf, PathBuf)>,
+}
+
+impl Pending {
+    /// Creates a temporary file next to `path`, e.g. `file_bundle.txt.1234.tmp`.
+    pub fn create(&mut self, path: PathBuf) -> io::Result<File> {
+        let mut temporary = path.clone().into_os_string();
+        temporary.push(format!(".{}.tmp", std::process::id()));
+        let temporary = PathBuf::from(temporary);
+        register(temporary.clone());
+        self.files.push((temporary.clone(), path));
+        File::create(&temporary)
+    }
+
+    /// Flushes every file to disk and renames it into place. All writers of
+    /// the files must have been flushed.
+    pub fn commit(&mut self) -> io::Result<()> {
+        for (temporary, _) in &self.files {
+            OpenOptions::new().write(true).open(temporary)?.sync_all()?;
+        }
+        for (temporary, path) in &self.files {
+            fs::rename(temporary, path)?;
+        }
+        for (temporary, path) in self.files.drain(..) {
+            unregister(&temporary);
+            sync_dir(path.parent().unwrap_or(Path::new(".")));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        for (temporary, _) in self.files.drain(..) {
+            let _ = fs::remove_file(&temporary);
+            unregister(&temporary);
+        }
+    }
+}
+
+/// Has `path` removed if the user presses Ctrl-C before `unregister` is called.
+pub fn register(path: PathBuf) {
+    lock().push(path);
+}
+
+pub fn unregister(path: &Path) {
+    lock().retain(|pending| pending != path);
+}
+
+fn lock() -> std::sync::MutexGuard<'static, Vec<PathBuf>> {
+    TEMPORARIES.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Makes a rename durable. Directories cannot be opened for syncing on every
+/// platform, so this is best effort.
+fn sync_dir(dir: &Path) {
+    if cfg!(unix) {
+        let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        if let Ok(dir) = File::open(dir) {
+            let _ = dir.sync_all();
+        }
+    }
+}
@@ -0,0 +1,295 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use clap::{Args, Subcommand};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::content::{self, LoadOptions, Source, Stats};
+use crate::format::Renderer;
+use crate::tokens::Tokenizer;
+
+/// Overrides the directory the cache is kept in.
+pub const DIR_VARIABLE: &str = "FBUNDLE_CACHE_DIR";
+
+/// Current version of the entry layout; entries of other versions are ignored.
+const VERSION: u32 = 2;
+
+/// Arguments for `fbundle cache`.
+#[derive(Debug, Args)]
+pub struct CacheArgs {
+    #[command(subcommand)]
+    pub action: CacheAction,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CacheAction {
+    /// Remove every cached file
+    Clean,
+}
+
+/// The directory the cache is kept in: `$FBUNDLE_CACHE_DIR`, or `fbundle` in
+/// the user's cache directory.
+pub fn dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os(DIR_VARIABLE).filter(|dir| !dir.is_empty()) {
+        return Some(PathBuf::from(dir));
+    }
+    let base = if cfg!(windows) {
+        env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|home| Path::new(&home).join("Library/Caches"))
+    } else {
+        env::var_os("XDG_CACHE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+    };
+    base.map(|base| base.join("fbundle"))
+}
+
+/// What the cache knows about a file, stored as `<key>.json`, next to its body
+/// in `<key>.body` if the body is not the file itself.
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    version: u32,
+    /// The canonical path, to rule out collisions of the key.
+    path: PathBuf,
+    /// The state of the file the entry was made from.
+    stamp: Stamp,
+    sha256: String,
+    /// `None` if the binary policy left the file out.
+    block: Option<Block>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Block {
+    encoding: String,
+    stats: Stats,
+    /// Whether the body is stored in `<key>.body`. UTF-8 files are streamed
+    /// from where they are instead.
+    stored: bool,
+    /// Token counts of the rendered block, by `token_key`.
+    #[serde(default)]
+    tokens: BTreeMap<String, usize>,
+}
+
+/// What tells an unchanged file from a changed one without reading it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stamp {
+    size: u64,
+    /// Nanoseconds since the Unix epoch.
+    mtime: Option<u128>,
+    inode: Option<(u64, u64)>,
+}
+
+impl Stamp {
+    /// Taken before a file is inspected, so that a file that changes while it
+    /// is read is not cached.
+    pub fn of(path: &Path) -> io::Result<Self> {
+        let meta = fs::metadata(path)?;
+        let mtime = meta.modified().ok().and_then(|time| time.duration_since(UNIX_EPOCH).ok()).map(|time| time.as_nanos());
+        Ok(Stamp { size: meta.len(), mtime, inode: inode(&meta) })
+    }
+}
+
+#[cfg(unix)]
+fn inode(meta: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn inode(_meta: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Inspected files from earlier runs, so that unchanged files are neither
+/// read nor transcoded, encoded or tokenized again. Entries are keyed by the
+/// file's path and the options it was read with, and are only used while the
+/// file's size, mtime and inode are the same as when it was cached.
+#[derive(Debug)]
+pub struct Cache {
+    dir: PathBuf,
+    /// The `LoadOptions` that change how a file is turned into a block.
+    options: String,
+}
+
+impl Cache {
+    pub fn open(dir: PathBuf, options: LoadOptions) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(Cache { dir, options: format!("{:?}", options) })
+    }
+
+    /// Where the entry of `path` is kept, without the extension.
+    fn key(&self, path: &Path) -> io::Result<(PathBuf, PathBuf)> {
+        let path = path.canonicalize()?;
+        let digest = Sha256::digest(format!("{}\0{}", path.display(), self.options).as_bytes());
+        Ok((path, self.dir.join(content::to_hex(&digest[..16]))))
+    }
+
+    fn read(&self, key: &Path) -> Option<Entry> {
+        let entry: Entry = serde_json::from_slice(&fs::read(key.with_extension("json")).ok()?).ok()?;
+        (entry.version == VERSION).then_some(entry)
+    }
+
+    /// The inspected file from an earlier run: `Some(None)` if it was left out,
+    /// `None` if the cache has nothing current for it.
+    pub fn get(&self, path: &Path, label: &str) -> Option<Option<Source>> {
+        let (canonical, key) = self.key(path).ok()?;
+        let entry = self.read(&key).filter(|entry| entry.path == canonical)?;
+        if entry.stamp != Stamp::of(path).ok()? {
+            return None;
+        }
+        let Some(block) = entry.block else {
+            return Some(None);
+        };
+        let body = block.stored.then(|| key.with_extension("body"));
+        if let Some(body) = &body {
+            if fs::metadata(body).ok()?.len() != block.stats.bytes {
+                return None;
+            }
+        }
+        let encoding = content::encoding_name(&block.encoding)?;
+        let source = Source::cached(path.to_path_buf(), label.to_string(), entry.stamp.size, encoding, block.stats, entry.sha256, body);
+        Some(Some(source))
+    }
+
+    /// Stores the result of inspecting `path` in the state `stamp`. The file is
+    /// read once more to hash it, and its body is copied into the cache only if
+    /// it is transcoded or encoded: a UTF-8 file is its own body.
+    pub fn put(&self, path: &Path, stamp: Stamp, source: Option<&Source>) -> io::Result<()> {
+        let (canonical, key) = self.key(path)?;
+        let sha256 = match source.and_then(|source| source.sha256.clone()) {
+            Some(sha256) => sha256,
+            None => content::sha256_file(path)?,
+        };
+        let block = match source {
+            Some(source) => {
+                let body = key.with_extension("body");
+                let stored = !source.is_verbatim();
+                if stored {
+                    write_atomic(&body, |out| source.read_body(|chunk| out.write_all(chunk.as_bytes())))?;
+                } else if let Err(err) = fs::remove_file(&body) {
+                    if err.kind() != io::ErrorKind::NotFound {
+                        return Err(err);
+                    }
+                }
+                let (encoding, stats) = (source.encoding.to_string(), source.stats.clone());
+                Some(Block { encoding, stats, stored, tokens: BTreeMap::new() })
+            }
+            None => None,
+        };
+        if Stamp::of(path)? != stamp {
+            return Ok(());
+        }
+        let entry = Entry { version: VERSION, path: canonical, stamp, sha256, block };
+        self.write(&key, &entry)
+    }
+
+    /// The token count of the rendered block of `source` stored under `key`.
+    pub fn tokens(&self, source: &Source, key: &str) -> Option<usize> {
+        let (_, entry) = self.current(source)?;
+        entry.block?.tokens.get(key).copied()
+    }
+
+    pub fn put_tokens(&self, source: &Source, key: String, tokens: usize) -> io::Result<()> {
+        let Some((path, mut entry)) = self.current(source) else {
+            return Ok(());
+        };
+        if let Some(block) = entry.block.as_mut() {
+            block.tokens.insert(key, tokens);
+        }
+        self.write(&path, &entry)
+    }
+
+    /// The entry of `source` if it is still current.
+    fn current(&self, source: &Source) -> Option<(PathBuf, Entry)> {
+        let (canonical, key) = self.key(&source.path).ok()?;
+        let entry = self.read(&key).filter(|entry| entry.path == canonical)?;
+        (entry.stamp == Stamp::of(&source.path).ok()?).then_some((key, entry))
+    }
+
+    fn write(&self, key: &Path, entry: &Entry) -> io::Result<()> {
+        write_atomic(&key.with_extension("json"), |out| serde_json::to_writer(out, entry).map_err(io::Error::other))
+    }
+}
+
+/// Identifies how a block is rendered and counted, so token counts are only
+/// reused for the same tokenizer, format, templates, label and position, and
+/// block number if the templates show it.
+pub fn token_key(renderer: &Renderer, tokenizer: Tokenizer, source: &Source, first: bool) -> String {
+    let mut key = format!("{}\0{:?}\0{}\0{}", tokenizer.name(), renderer, source.label, first);
+    if renderer.numbers_blocks() {
+        key.push_str(&format!("\0{}/{}", source.index, source.count));
+    }
+    content::to_hex(&Sha256::digest(key.as_bytes())[..16])
+}
+
+/// Writes `path` through a temporary file, so that runs in parallel never
+/// see half an entry.
+fn write_atomic(path: &Path, write: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>) -> io::Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(format!(".{}.tmp", std::process::id()));
+    let temporary = PathBuf::from(temporary);
+    let result = File::create(&temporary).and_then(|file| {
+        let mut out = BufWriter::new(file);
+        write(&mut out)?;
+        out.into_inner().map_err(|err| err.into_error())?.sync_all()
+    });
+    match result.and_then(|()| fs::rename(&temporary, path)) {
+        Ok(()) => Ok(()),
+        Err(err) => {
+            let _ = fs::remove_file(&temporary);
+            Err(err)
+        }
+    }
+}
+
+pub fn run(args: CacheArgs) -> io::Result<()> {
+    match args.action {
+        CacheAction::Clean => {
+            let Some(dir) = dir() else {
+                return Err(io::Error::new(io::ErrorKind::NotFound, format!("no cache directory: set {} or HOME", DIR_VARIABLE)));
+            };
+            let (mut files, mut bytes) = (0, 0);
+            match fs::read_dir(&dir) {
+                Ok(entries) => {
+                    // The directory may be shared, so only remove what the cache wrote.
+                    for entry in entries {
+                        let entry = entry?;
+                        if !entry.file_type()?.is_file() || !is_cache_file(&entry.file_name().to_string_lossy()) {
+                            continue;
+                        }
+                        bytes += entry.metadata()?.len();
+                        files += 1;
+                        fs::remove_file(entry.path())?;
+                    }
+                    if fs::read_dir(&dir)?.next().is_none() {
+                        fs::remove_dir(&dir)?;
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+            println!("Removed {} cache files ({} bytes) from: {}", files, bytes, dir.display());
+            Ok(())
+        }
+    }
+}
+
+/// Whether `name` is a file the cache writes: `<key>.json`, `<key>.body`, or
+/// either of them with `.<pid>.tmp` appended while it is written.
+fn is_cache_file(name: &str) -> bool {
+    let parts: Vec<&str> = name.split('.').collect();
+    let key = |part: &str| part.len() == 32 && part.bytes().all(|b| b.is_ascii_hexdigit());
+    let pid = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+    match parts.as_slice() {
+        [name, "json" | "body"] => key(name),
+        [name, "json" | "body", process, "tmp"] => key(name) && pid(process),
+        _ => false,
+    }
+}
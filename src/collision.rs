@@ -0,0 +1,234 @@
+use std::borrow::Cow;
+use std::collections::{BTreeSet, VecDeque};
+use std::io;
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::content::Source;
+use crate::template::Template;
+
+/// What to do with lines of a file that would be read back as a file header
+/// or footer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SepCollision {
+    /// Warn about them and write the bundle anyway
+    #[default]
+    Warn,
+    /// Fail without writing the bundle
+    Fail,
+    /// Put a backslash in front of them, which 'unbundle --unescape' removes
+    Escape,
+}
+
+/// The lines that mark where files begin and end in a plain or Markdown bundle.
+#[derive(Debug, Clone, Copy)]
+pub struct Markers<'a> {
+    pub file_sep: &'a Template,
+    pub header: &'a Template,
+    pub footer: Option<&'a Template>,
+}
+
+impl Markers<'_> {
+    /// How many lines a header or footer spans at most; a separator with
+    /// newlines makes them span several.
+    pub fn span(&self) -> usize {
+        let footer = self.footer.map_or(0, |footer| footer.lines(self.file_sep));
+        self.header.lines(self.file_sep).max(footer)
+    }
+
+    /// Whether `window`, `span()` lines (fewer at the end of a file) without
+    /// the last newline, starts with a header or footer.
+    pub fn collides(&self, window: &str) -> bool {
+        let starts_with = |template: &Template| {
+            first_lines(window, template.lines(self.file_sep)).is_some_and(|lines| template.parse_line(lines, self.file_sep).is_some())
+        };
+        starts_with(self.header) || self.footer.is_some_and(starts_with)
+    }
+
+    /// Whether the first line of `window` is escaped by `escape`: the window
+    /// collides, possibly behind backslashes, so that escaping can be undone exactly.
+    pub fn needs_escape(&self, window: &str) -> bool {
+        self.collides(window.trim_start_matches('\\'))
+    }
+
+    /// Puts a backslash in front of every line that needs it.
+    pub fn escape<'t>(&self, text: &'t str) -> Cow<'t, str> {
+        let mut lines = split_lines(text);
+        let escaped: Vec<usize> =
+            (0..lines.len()).filter(|&i| self.needs_escape(&window(&lines, i, self.span()))).collect();
+        if escaped.is_empty() {
+            return Cow::Borrowed(text);
+        }
+        for i in escaped {
+            lines[i] = Cow::Owned(format!("\\{}", lines[i]));
+        }
+        Cow::Owned(join_lines(&lines, text))
+    }
+
+    /// Undoes `escape`. Lines are restored from the last, so that every
+    /// window is checked with the original text after its first line, as
+    /// `escape` checked it.
+    pub fn unescape<'t>(&self, text: &'t str) -> Cow<'t, str> {
+        let mut lines = split_lines(text);
+        let mut changed = false;
+        for i in (0..lines.len()).rev() {
+            if lines[i].starts_with('\\') && self.needs_escape(&window(&lines, i, self.span())) {
+                lines[i] = Cow::Owned(lines[i][1..].to_string());
+                changed = true;
+            }
+        }
+        if !changed {
+            return Cow::Borrowed(text);
+        }
+        Cow::Owned(join_lines(&lines, text))
+    }
+}
+
+/// The first `count` lines of `text`, without the newline after them.
+fn first_lines(text: &str, count: usize) -> Option<&str> {
+    let mut start = 0;
+    for _ in 1..count {
+        start += text[start..].find('\n')? + 1;
+    }
+    let end = text[start..].find('\n').map_or(text.len(), |len| start + len);
+    (count > 0).then(|| &text[..end])
+}
+
+/// The lines of `text` without their newlines.
+fn split_lines(text: &str) -> Vec<Cow<'_, str>> {
+    text.split_inclusive('\n').map(|line| Cow::Borrowed(line.strip_suffix('\n').unwrap_or(line))).collect()
+}
+
+/// Puts lines split by `split_lines` back together, ending in a newline if `original` did.
+fn join_lines(lines: &[Cow<'_, str>], original: &str) -> String {
+    let mut text = lines.join("\n");
+    if original.ends_with('\n') {
+        text.push('\n');
+    }
+    text
+}
+
+/// Up to `span` lines from line `i` on, joined by newlines.
+fn window(lines: &[Cow<'_, str>], i: usize, span: usize) -> String {
+    lines[i..lines.len().min(i + span)].join("\n")
+}
+
+/// What `suspicious_lines` found in a file.
+#[derive(Debug, Default)]
+pub struct Suspicious {
+    /// The windows (see `Markers::collides`), numbered by their first line
+    /// from 1, that need escaping with the separator as given.
+    pub windows: Vec<(usize, String)>,
+    /// For `--sep-auto`: how often `unique_sep` can lengthen the separator
+    /// so that a window needs escaping.
+    pub taken: BTreeSet<usize>,
+}
+
+/// Reads `source` once and finds the windows that could collide with
+/// `markers`, and with `sep_auto`, the longer separators made by `unique_sep`
+/// some window would collide with. Only colliding windows are kept, so that
+/// a large file with the separator's text in every line is not held in memory.
+pub fn suspicious_lines(source: &Source, markers: &Markers, sep_auto: bool) -> io::Result<Suspicious> {
+    let span = markers.span();
+    // The separator is lengthened by repeating the last character of its
+    // marker, so a window can only collide with a longer one if it has a
+    // longer run of that character after the rest of the marker.
+    let marker = markers.file_sep.marker().filter(|_| sep_auto).and_then(|marker| {
+        let c = marker.chars().last()?;
+        let base = marker.trim_end_matches(c);
+        Some((base, c, marker[base.len()..].chars().count()))
+    });
+    let mut lengthened = vec![markers.file_sep.clone()];
+    let mut suspicious = Suspicious::default();
+    let mut recent: VecDeque<String> = VecDeque::with_capacity(span);
+    let mut number = 0;
+    let mut check = |window: &str, number: usize| {
+        if markers.needs_escape(window) {
+            suspicious.windows.push((number, window.to_string()));
+        }
+        let Some((base, c, run)) = marker else {
+            return;
+        };
+        for added in 0..(longest_run(window, base, c) + 1).saturating_sub(run) {
+            while lengthened.len() <= added {
+                let mut file_sep = lengthened[lengthened.len() - 1].clone();
+                file_sep.lengthen();
+                lengthened.push(file_sep);
+            }
+            let longer = Markers { file_sep: &lengthened[added], ..*markers };
+            if longer.needs_escape(window) {
+                suspicious.taken.insert(added);
+            }
+        }
+    };
+    let mut push = |line: &str| {
+        number += 1;
+        if span == 1 {
+            return check(line, number);
+        }
+        recent.push_back(line.to_string());
+        if recent.len() == span {
+            check(&recent.make_contiguous().join("\n"), number + 1 - span);
+            recent.pop_front();
+        }
+    };
+
+    let mut partial = String::new();
+    // Chunks end at line ends, except for lines longer than a chunk.
+    source.read_body(|chunk| {
+        for piece in chunk.split_inclusive('\n') {
+            match piece.strip_suffix('\n') {
+                Some(line) if partial.is_empty() => push(line),
+                Some(line) => {
+                    partial.push_str(line);
+                    push(&partial);
+                    partial.clear();
+                }
+                None => partial.push_str(piece),
+            }
+        }
+        Ok(())
+    })?;
+    if !partial.is_empty() {
+        push(&partial);
+    }
+    // The windows at the end of the file are shorter.
+    let lines: Vec<String> = recent.into_iter().collect();
+    for i in 0..lines.len() {
+        check(&lines[i..].join("\n"), number + 1 - lines.len() + i);
+    }
+    Ok(suspicious)
+}
+
+/// The length of the longest run of `c` in `window` that follows `base`.
+fn longest_run(window: &str, base: &str, c: char) -> usize {
+    let (mut longest, mut start, mut len) = (0, 0, 0);
+    for (at, next) in window.char_indices().map(|(at, next)| (at, Some(next))).chain([(window.len(), None)]) {
+        if next == Some(c) {
+            if len == 0 {
+                start = at;
+            }
+            len += 1;
+            continue;
+        }
+        if len > longest && window[..start].ends_with(base) {
+            longest = len;
+        }
+        len = 0;
+    }
+    longest
+}
+
+/// The shortest separator no window collides with: the separator of
+/// `markers`, with the last character of its marker repeated as often as
+/// needed, skipping the lengthenings in `taken` (see `Suspicious`).
+pub fn unique_sep(markers: &Markers, taken: &BTreeSet<usize>) -> Template {
+    let mut file_sep = markers.file_sep.clone();
+    let mut added = 0;
+    while taken.contains(&added) && file_sep.lengthen() {
+        added += 1;
+    }
+    file_sep
+}
@@ -0,0 +1,181 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use clap::parser::ValueSource;
+use clap::ArgMatches;
+use serde::Deserialize;
+
+use crate::FileBundle;
+
+/// Name of the project config file, searched for in the source directory and its parents.
+pub const FILE_NAME: &str = "fbundle.toml";
+
+/// The contents of `fbundle.toml`: top-level settings are defaults for every
+/// run, and each `[profile.<name>]` table overrides them when selected with `--profile`.
+#[derive(Debug, Deserialize)]
+struct ConfigFile {
+    #[serde(default)]
+    profile: BTreeMap<String, toml::Table>,
+    #[serde(flatten)]
+    defaults: toml::Table,
+}
+
+/// Looks for `fbundle.toml` in `src_dir` and its parents. Returns the path of
+/// the file and its directory, the latter expressed relative to `src_dir` (e.g.
+/// `src/..`) so that paths in the config can be resolved without turning every
+/// path in the bundle absolute.
+fn find(src_dir: &Path) -> io::Result<Option<(PathBuf, PathBuf)>> {
+    let start = src_dir.canonicalize()?;
+    let mut dir = src_dir.to_path_buf();
+    for ancestor in start.ancestors() {
+        if ancestor.join(FILE_NAME).is_file() {
+            return Ok(Some((dir.join(FILE_NAME), dir)));
+        }
+        dir.push("..");
+    }
+    Ok(None)
+}
+
+/// Loads `fbundle.toml` and fills in every setting of `args` that was not given
+/// on the command line, first from the selected profile and then from the
+/// top-level defaults. Returns the names of the settings taken from the config.
+pub fn apply(args: &mut FileBundle, matches: &ArgMatches) -> io::Result<Vec<&'static str>> {
+    let Some((path, dir)) = find(&args.src_dir)? else {
+        return match &args.profile {
+            Some(profile) => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("--profile {} given, but no {} found in {} or its parents", profile, FILE_NAME, args.src_dir.display()),
+            )),
+            None => Ok(Vec::new()),
+        };
+    };
+
+    let text = fs::read_to_string(&path)?;
+    let mut config: ConfigFile = toml::from_str(&text).map_err(|err| invalid_data(&path, err.to_string()))?;
+
+    check_keys(&path, "", &config.defaults)?;
+    for (name, profile) in &config.profile {
+        check_keys(&path, &format!("[profile.{}] ", name), profile)?;
+    }
+
+    let mut settings = config.defaults;
+    if let Some(name) = &args.profile {
+        let Some(profile) = config.profile.remove(name) else {
+            let known: Vec<&str> = config.profile.keys().map(String::as_str).collect();
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("no profile '{}' in {} (available: {})", name, path.display(), known.join(", ")),
+            ));
+        };
+        settings.extend(profile);
+    }
+
+    let from_config = assign(args, matches, settings)
+        .map_err(|(key, err)| invalid_data(&path, format!("invalid value for '{}': {}", key, err.message())))?;
+
+    // Paths in the config are relative to the directory that holds it.
+    if from_config.contains(&"src_dir") {
+        resolve(&dir, &mut args.src_dir);
+    }
+    if from_config.contains(&"out_dir") {
+        resolve(&dir, &mut args.out_dir);
+    }
+    if let Some(files_from) = args.files_from.as_mut().filter(|_| from_config.contains(&"files_from")) {
+        resolve(&dir, files_from);
+    }
+
+    if args.verbose {
+        status!(args, "Using config: {}", path.display());
+    }
+    Ok(from_config)
+}
+
+macro_rules! settings {
+    ($($field:ident),* $(,)?) => {
+        /// The settings `fbundle.toml` may contain, named like the fields of `FileBundle`.
+        const SETTINGS: &[&str] = &[$(stringify!($field)),*];
+
+        /// Sets every field found in `settings` that was not given on the command
+        /// line. Returns the names of the fields that were set.
+        fn assign(
+            args: &mut FileBundle,
+            matches: &ArgMatches,
+            mut settings: toml::Table,
+        ) -> Result<Vec<&'static str>, (&'static str, toml::de::Error)> {
+            let mut assigned = Vec::new();
+            $(
+                if let Some(value) = settings.remove(stringify!($field)) {
+                    if matches.value_source(stringify!($field)) != Some(ValueSource::CommandLine) {
+                        args.$field = value.try_into().map_err(|err| (stringify!($field), err))?;
+                        assigned.push(stringify!($field));
+                    }
+                }
+            )*
+            Ok(assigned)
+        }
+    };
+}
+
+settings!(
+    bundle_name,
+    src_dir,
+    out_dir,
+    dst_ext,
+    file_sep,
+    preset,
+    src_globs,
+    files_from,
+    git_tracked,
+    git_changed,
+    git_diff,
+    git_rev,
+    diff_against,
+    diff_context,
+    respect_gitignore,
+    respect_ignore_files,
+    custom_ignore_file,
+    sort,
+    priority,
+    binary,
+    input_encoding,
+    lossy,
+    format,
+    max_bytes,
+    max_tokens,
+    overflow,
+    tokenizer,
+    count_tokens,
+    path_style,
+    sep_auto,
+    sep_collision,
+    header_template,
+    footer_template,
+    manifest,
+    tree,
+    tree_info,
+    tree_excluded,
+    no_cache,
+    watch,
+    verbose,
+);
+
+fn check_keys(path: &Path, section: &str, table: &toml::Table) -> io::Result<()> {
+    match table.keys().find(|key| !SETTINGS.contains(&key.as_str())) {
+        Some(key) => Err(invalid_data(path, format!("{}unknown setting '{}'", section, key))),
+        None => Ok(()),
+    }
+}
+
+fn invalid_data(path: &Path, message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), message))
+}
+
+/// Makes a relative path from the config file relative to the current directory.
+/// `-` (stdin/stdout) is left alone.
+fn resolve(dir: &Path, path: &mut PathBuf) {
+    if path.is_relative() && path.as_os_str() != "-" && dir != Path::new(".") {
+        *path = dir.join(&*path);
+    }
+}
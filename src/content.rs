@@ -0,0 +1,511 @@
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use base64::Engine as _;
+use chardetng::EncodingDetector;
+use clap::ValueEnum;
+use encoding_rs::{CoderResult, Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::format::{DIFF, TEXT};
+
+/// Size of the buffer files are streamed through.
+const BUFFER_LEN: usize = 64 * 1024;
+
+/// How many leading bytes are inspected to decide whether a file is binary.
+const SNIFF_LEN: usize = 8192;
+
+/// Width of a line of base64 or hex in the bundle.
+const LINE_WIDTH: usize = 76;
+
+/// Input bytes per line of base64 and hex output.
+const BASE64_LINE_BYTES: usize = LINE_WIDTH / 4 * 3;
+const HEX_LINE_BYTES: usize = LINE_WIDTH / 2;
+
+/// What to do with files that are not text.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum BinaryPolicy {
+    /// Leave the file out of the bundle
+    Skip,
+    /// Write a single line with the file's size and SHA-256 hash
+    #[default]
+    Placeholder,
+    /// Embed the contents as base64
+    Base64,
+    /// Embed the contents as hex
+    Hex,
+}
+
+/// How files are read and turned into blocks.
+#[derive(Debug, Clone, Copy)]
+pub struct LoadOptions {
+    pub binary: BinaryPolicy,
+    /// Decode text that is not UTF-8 with this encoding instead of guessing.
+    pub input_encoding: Option<&'static Encoding>,
+    /// Keep text that does not decode cleanly, with U+FFFD for the bad bytes.
+    pub lossy: bool,
+}
+
+/// Looks up an encoding by any of its WHATWG labels, e.g. `latin1` or `utf-16le`.
+pub fn encoding_for_label(label: &str) -> io::Result<&'static Encoding> {
+    Encoding::for_label(label.trim().as_bytes())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("unknown encoding: {}", label)))
+}
+
+/// Facts about the body of a block, gathered while inspecting the file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Stats {
+    /// Length of the body in bytes.
+    pub bytes: u64,
+    pub chars: u64,
+    pub lines: u64,
+    /// Longest run of backticks, so a Markdown fence can be made longer.
+    pub longest_backtick_run: usize,
+    pub ends_with_newline: bool,
+    #[serde(skip)]
+    backtick_run: usize,
+}
+
+impl Stats {
+    fn feed(&mut self, chunk: &str) {
+        self.bytes += chunk.len() as u64;
+        for c in chunk.chars() {
+            self.chars += 1;
+            match c {
+                '\n' => self.lines += 1,
+                '`' => {
+                    self.backtick_run += 1;
+                    self.longest_backtick_run = self.longest_backtick_run.max(self.backtick_run);
+                    continue;
+                }
+                _ => {}
+            }
+            self.backtick_run = 0;
+        }
+        if !chunk.is_empty() {
+            self.ends_with_newline = chunk.ends_with('\n');
+        }
+    }
+
+    fn finish(mut self) -> Self {
+        if self.bytes > 0 && !self.ends_with_newline {
+            self.lines += 1;
+        }
+        self
+    }
+
+    fn of(text: &str) -> Self {
+        let mut stats = Stats::default();
+        stats.feed(text);
+        stats.finish()
+    }
+
+    /// Stats of `len` bytes of base64 or hex wrapped at `LINE_WIDTH`.
+    fn wrapped(len: u64) -> Self {
+        let lines = len.div_ceil(LINE_WIDTH as u64);
+        Stats { bytes: len + lines, chars: len + lines, lines, ends_with_newline: lines > 0, ..Stats::default() }
+    }
+}
+
+/// Where the body of a block comes from.
+#[derive(Debug, Clone)]
+enum Body {
+    /// The file is UTF-8 and copied as is.
+    Utf8,
+    /// The file is text in another encoding and transcoded while copying.
+    Transcode(&'static Encoding),
+    Base64,
+    Hex,
+    /// The body is held in memory.
+    Text(String),
+    /// The body was stored in the cache by an earlier run and is copied from there.
+    Cached(PathBuf),
+}
+
+/// A file that has been inspected and is ready to be streamed into the bundle.
+#[derive(Debug, Clone)]
+pub struct Source {
+    /// The file on disk.
+    pub path: PathBuf,
+    /// The path shown in the header.
+    pub label: String,
+    /// Size of the file in bytes.
+    pub size: u64,
+    pub encoding: &'static str,
+    pub stats: Stats,
+    /// SHA-256 of the file, if a header or footer template needs it.
+    pub sha256: Option<String>,
+    /// Position of the block in the bundle, from 1, for `{index}`; 0 until known.
+    pub index: usize,
+    /// Number of blocks in the bundle, for `{count}`; 0 until known.
+    pub count: usize,
+    body: Body,
+}
+
+impl Source {
+    /// A block made from `text` rather than read from a file, such as an embedded manifest.
+    pub fn text(label: String, text: String) -> Self {
+        Source {
+            path: PathBuf::from(&label),
+            label,
+            size: text.len() as u64,
+            encoding: TEXT,
+            stats: Stats::of(&text),
+            sha256: Some(to_hex(&Sha256::digest(text.as_bytes()))),
+            index: 0,
+            count: 0,
+            body: Body::Text(text),
+        }
+    }
+
+    /// A block whose body is read from `body`, a UTF-8 file in the cache, or
+    /// from the file itself if it is UTF-8 and was not copied.
+    pub fn cached(
+        path: PathBuf,
+        label: String,
+        size: u64,
+        encoding: &'static str,
+        stats: Stats,
+        sha256: String,
+        body: Option<PathBuf>,
+    ) -> Self {
+        let body = body.map_or(Body::Utf8, Body::Cached);
+        Source { path, label, size, encoding, stats, sha256: Some(sha256), index: 0, count: 0, body }
+    }
+
+    /// Whether the body is the file's own bytes, streamed as they are.
+    pub fn is_verbatim(&self) -> bool {
+        matches!(self.body, Body::Utf8)
+    }
+
+    /// The same block with `text` as its body.
+    pub fn with_text(self, text: String) -> Self {
+        Source { stats: Stats::of(&text), body: Body::Text(text), ..self }
+    }
+
+    /// The same block with a unified diff of the file as its body.
+    pub fn into_diff(self, diff: String) -> Self {
+        Source { encoding: DIFF, ..self.with_text(diff) }
+    }
+
+    /// The whole body as one string.
+    pub fn body_text(&self) -> io::Result<String> {
+        let mut text = String::new();
+        self.read_body(|chunk| {
+            text.push_str(chunk);
+            Ok(())
+        })?;
+        Ok(text)
+    }
+
+    /// Streams the body to `emit` in chunks of at most a few buffers. Chunks
+    /// end on character boundaries and, unless a line is longer than the
+    /// buffer, at the end of a line.
+    pub fn read_body(&self, mut emit: impl FnMut(&str) -> io::Result<()>) -> io::Result<()> {
+        match &self.body {
+            Body::Text(text) if text.is_empty() => Ok(()),
+            Body::Text(text) => emit(text),
+            Body::Utf8 => stream_utf8(File::open(&self.path)?, emit),
+            Body::Cached(body) => stream_utf8(File::open(body)?, emit),
+            Body::Transcode(encoding) => stream_decoded(File::open(&self.path)?, encoding, emit).map(|_| ()),
+            Body::Base64 => stream_encoded(File::open(&self.path)?, BASE64_LINE_BYTES, emit, |bytes| {
+                base64::engine::general_purpose::STANDARD.encode(bytes)
+            }),
+            Body::Hex => stream_encoded(File::open(&self.path)?, HEX_LINE_BYTES, emit, to_hex),
+        }
+    }
+}
+
+/// Works out how `path` should be bundled, reading it once with a fixed-size
+/// buffer. Returns `None` if the binary policy says to leave it out.
+///
+/// A UTF-16 byte order mark wins over everything else. Otherwise the first
+/// block is sniffed for binary content, UTF-8 is tried, and only then is the
+/// `--input-encoding` applied or a legacy encoding guessed.
+pub fn inspect(path: &Path, label: String, options: LoadOptions) -> io::Result<Option<Source>> {
+    let size = fs::metadata(path)?.len();
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    File::open(path)?.take(SNIFF_LEN as u64).read_to_end(&mut head)?;
+
+    let bom = Encoding::for_bom(&head).map(|(encoding, _)| encoding).filter(|&encoding| encoding != UTF_8);
+    let forced_utf16 = options.input_encoding.is_some_and(|encoding| encoding == UTF_16LE || encoding == UTF_16BE);
+
+    if bom.is_some() || forced_utf16 || !looks_binary(&head) {
+        if let Some((encoding, stats, body)) = inspect_text(path, bom, options)? {
+            return Ok(Some(Source { path: path.to_path_buf(), label, size, encoding, stats, sha256: None, index: 0, count: 0, body }));
+        }
+    }
+
+    let (encoding, stats, body) = match options.binary {
+        BinaryPolicy::Skip => return Ok(None),
+        BinaryPolicy::Placeholder => {
+            let text = format!("[binary file: {} bytes, sha256 {}]\n", size, sha256_file(path)?);
+            ("binary", Stats::of(&text), Body::Text(text))
+        }
+        BinaryPolicy::Base64 => ("base64", Stats::wrapped(size.div_ceil(3) * 4), Body::Base64),
+        BinaryPolicy::Hex => ("hex", Stats::wrapped(size * 2), Body::Hex),
+    };
+    Ok(Some(Source { path: path.to_path_buf(), label, size, encoding, stats, sha256: None, index: 0, count: 0, body }))
+}
+
+fn inspect_text(
+    path: &Path,
+    bom: Option<&'static Encoding>,
+    options: LoadOptions,
+) -> io::Result<Option<(&'static str, Stats, Body)>> {
+    // UTF-16 text is often valid UTF-8 as well, so a forced UTF-16 wins like a byte order mark.
+    let forced_utf16 = options.input_encoding.filter(|&encoding| encoding == UTF_16LE || encoding == UTF_16BE);
+    let encoding = match bom.or(forced_utf16) {
+        Some(encoding) => encoding,
+        None => {
+            // The common case: a single pass that validates UTF-8 and gathers stats.
+            let mut stats = Stats::default();
+            let utf8 = stream_utf8(File::open(path)?, |chunk| {
+                stats.feed(chunk);
+                Ok(())
+            });
+            match (utf8, options.input_encoding) {
+                // --input-encoding is only for text that is not UTF-8.
+                (Ok(()), _) => return Ok(Some((TEXT, stats.finish(), Body::Utf8))),
+                (Err(err), _) if err.kind() != io::ErrorKind::InvalidData => return Err(err),
+                (Err(_), Some(encoding)) => encoding,
+                (Err(_), None) => guess_encoding(path)?,
+            }
+        }
+    };
+
+    let mut stats = Stats::default();
+    let had_errors = stream_decoded(File::open(path)?, encoding, |chunk| {
+        stats.feed(chunk);
+        Ok(())
+    })?;
+    if had_errors {
+        if !options.lossy {
+            return Ok(None);
+        }
+        eprintln!(
+            "Warning: File {} is not valid {}. Replacing undecodable bytes.",
+            path.display(),
+            encoding.name(),
+        );
+    }
+    // Lossily decoded text cannot be turned back into the original bytes,
+    // so it is recorded as what it now is: UTF-8.
+    let name = if had_errors || encoding == UTF_8 { TEXT } else { encoding.name() };
+    Ok(Some((name, stats.finish(), Body::Transcode(encoding))))
+}
+
+fn guess_encoding(path: &Path) -> io::Result<&'static Encoding> {
+    let mut detector = EncodingDetector::new();
+    let mut file = File::open(path)?;
+    let mut buffer = vec![0; BUFFER_LEN];
+    loop {
+        let read = file.read(&mut buffer)?;
+        detector.feed(&buffer[..read], read == 0);
+        if read == 0 {
+            return Ok(detector.guess(None, false));
+        }
+    }
+}
+
+/// Turns an encoding as recorded by `inspect` back into the same `&'static str`.
+pub fn encoding_name(name: &str) -> Option<&'static str> {
+    match name {
+        TEXT => Some(TEXT),
+        "binary" => Some("binary"),
+        "base64" => Some("base64"),
+        "hex" => Some("hex"),
+        DIFF => Some(DIFF),
+        label => Encoding::for_label(label.as_bytes()).map(Encoding::name),
+    }
+}
+
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// Streams UTF-8 from `reader`, failing with `InvalidData` if it is not UTF-8.
+fn stream_utf8(mut reader: impl Read, mut emit: impl FnMut(&str) -> io::Result<()>) -> io::Result<()> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "stream did not contain valid UTF-8");
+    let mut buffer = vec![0; BUFFER_LEN];
+    let mut len = 0;
+    loop {
+        let read = reader.read(&mut buffer[len..])?;
+        len += read;
+
+        let end = if read == 0 {
+            len
+        } else if let Some(newline) = buffer[..len].iter().rposition(|&b| b == b'\n') {
+            newline + 1
+        } else if len == buffer.len() {
+            match std::str::from_utf8(&buffer[..len]) {
+                Ok(_) => len,
+                Err(err) if err.error_len().is_none() => err.valid_up_to(),
+                Err(_) => return Err(invalid()),
+            }
+        } else {
+            continue;
+        };
+
+        if end > 0 {
+            emit(std::str::from_utf8(&buffer[..end]).map_err(|_| invalid())?)?;
+            buffer.copy_within(end..len, 0);
+            len -= end;
+        }
+        if read == 0 {
+            return Ok(());
+        }
+    }
+}
+
+/// Streams text in `encoding` from `reader` as UTF-8, replacing malformed
+/// sequences. Returns whether anything had to be replaced.
+fn stream_decoded(
+    mut reader: impl Read,
+    encoding: &'static Encoding,
+    mut emit: impl FnMut(&str) -> io::Result<()>,
+) -> io::Result<bool> {
+    let mut decoder = encoding.new_decoder_with_bom_removal();
+    let mut input = vec![0; BUFFER_LEN];
+    let capacity = decoder.max_utf8_buffer_length(BUFFER_LEN).expect("buffer length fits in usize");
+    // Room for a whole decoded input buffer on top of an unflushed line.
+    let mut output = String::with_capacity(capacity + BUFFER_LEN);
+    let mut had_errors = false;
+
+    loop {
+        let read = reader.read(&mut input)?;
+        let last = read == 0;
+        let mut consumed = 0;
+        loop {
+            let (result, used, replaced) = decoder.decode_to_string(&input[consumed..read], &mut output, last);
+            consumed += used;
+            had_errors |= replaced;
+
+            if last || output.len() >= BUFFER_LEN {
+                if !output.is_empty() {
+                    emit(&output)?;
+                }
+                output.clear();
+            } else if let Some(newline) = output.rfind('\n') {
+                emit(&output[..=newline])?;
+                output.drain(..=newline);
+            }
+            if result == CoderResult::InputEmpty {
+                break;
+            }
+        }
+        if last {
+            return Ok(had_errors);
+        }
+    }
+}
+
+/// Streams the bytes from `reader` through `encode`, one wrapped line of
+/// `LINE_WIDTH` characters per `line_bytes` input bytes.
+fn stream_encoded(
+    mut reader: impl Read,
+    line_bytes: usize,
+    mut emit: impl FnMut(&str) -> io::Result<()>,
+    encode: impl Fn(&[u8]) -> String,
+) -> io::Result<()> {
+    let mut buffer = vec![0; line_bytes * (BUFFER_LEN / line_bytes)];
+    loop {
+        let mut len = 0;
+        while len < buffer.len() {
+            match reader.read(&mut buffer[len..])? {
+                0 => break,
+                read => len += read,
+            }
+        }
+        if len == 0 {
+            return Ok(());
+        }
+        emit(&wrap(&encode(&buffer[..len])))?;
+        if len < buffer.len() {
+            return Ok(());
+        }
+    }
+}
+
+/// Turns the body of a block back into the bytes of the original file:
+/// text is re-encoded in the encoding from its header and `base64` or `hex`
+/// bodies are decoded. Placeholders of binary files and diffs yield `None`.
+pub fn decode(encoding: &str, body: &str) -> io::Result<Option<Vec<u8>>> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let compact = || body.split_whitespace().collect::<String>();
+    let bytes = match encoding {
+        TEXT => body.as_bytes().to_vec(),
+        "binary" | DIFF => return Ok(None),
+        "base64" => base64::engine::general_purpose::STANDARD
+            .decode(compact())
+            .map_err(|err| invalid(err.to_string()))?,
+        "hex" => from_hex(&compact())?,
+        label => {
+            let encoding = encoding_for_label(label)?;
+            if encoding == UTF_16LE || encoding == UTF_16BE {
+                // encoding_rs has no UTF-16 encoder. Write the text back with a BOM.
+                let units = std::iter::once(0xfeff).chain(body.encode_utf16());
+                if encoding == UTF_16LE {
+                    units.flat_map(u16::to_le_bytes).collect()
+                } else {
+                    units.flat_map(u16::to_be_bytes).collect()
+                }
+            } else {
+                let (bytes, _, had_errors) = encoding.encode(body);
+                if had_errors {
+                    return Err(invalid(format!("contents cannot be represented in {}", encoding.name())));
+                }
+                bytes.into_owned()
+            }
+        }
+    };
+    Ok(Some(bytes))
+}
+
+/// Treats a file as binary if its first block contains a NUL byte or is more
+/// than 10% control characters.
+fn looks_binary(head: &[u8]) -> bool {
+    if head.contains(&0) {
+        return true;
+    }
+    let control = head
+        .iter()
+        .filter(|&&b| (b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r' | 0x0c)) || b == 0x7f)
+        .count();
+    control * 10 > head.len()
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        write!(hex, "{:02x}", byte).unwrap();
+    }
+    hex
+}
+
+fn from_hex(hex: &str) -> io::Result<Vec<u8>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid hex data");
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err(invalid());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid()))
+        .collect()
+}
+
+/// Breaks an encoded body into lines so it diffs and splits cleanly.
+fn wrap(encoded: &str) -> String {
+    let mut wrapped = String::with_capacity(encoded.len() + encoded.len() / LINE_WIDTH + 1);
+    for line in encoded.as_bytes().chunks(LINE_WIDTH) {
+        wrapped.push_str(std::str::from_utf8(line).unwrap());
+        wrapped.push('\n');
+    }
+    wrapped
+}
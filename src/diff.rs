@@ -0,0 +1,89 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use similar::TextDiff;
+
+use crate::collision::Markers;
+use crate::content::{self, BinaryPolicy, LoadOptions, Source};
+use crate::format::{self, Format};
+use crate::git::Snapshot;
+use crate::manifest;
+use crate::unbundle;
+
+/// What `--diff-against` compares the files with.
+#[derive(Debug)]
+pub enum Baseline {
+    /// Another directory, with the files at the same relative paths.
+    Dir(PathBuf),
+    /// The text of the files in an earlier bundle, by header path.
+    Bundle(HashMap<String, String>),
+    /// A git revision, read from the object database.
+    Rev(Snapshot),
+}
+
+impl Baseline {
+    /// Opens the baseline named by `spec`: a directory, a bundle file, or else
+    /// a git revision of the repository around `src_dir`. `wanted` are the
+    /// paths, relative to `src_dir`, that will be looked up. A bundle is parsed
+    /// with the separator and templates of this run, unescaped if `unescape`.
+    pub fn open(spec: &str, src_dir: &Path, wanted: &HashSet<PathBuf>, markers: &Markers, unescape: bool) -> io::Result<Self> {
+        let context = |err: io::Error| io::Error::new(err.kind(), format!("--diff-against {}: {}", spec, err));
+        let path = Path::new(spec);
+        if path.is_dir() {
+            return Ok(Baseline::Dir(path.to_path_buf()));
+        }
+        if path.is_file() {
+            let entries = unbundle::parse(&fs::read_to_string(path).map_err(context)?, markers, Format::Plain, unescape).map_err(context)?;
+            let files = unbundle::join_continued(entries)
+                .into_iter()
+                // Only blocks that hold the text of a file can be compared with it.
+                .filter(|entry| entry.path != manifest::EMBEDDED_PATH)
+                .filter(|entry| !format::is_binary(&entry.encoding) && entry.encoding != format::DIFF)
+                .map(|entry| (entry.path, entry.contents))
+                .collect();
+            return Ok(Baseline::Bundle(files));
+        }
+        Snapshot::extract(src_dir, spec, |path| wanted.contains(path)).map(Baseline::Rev).map_err(|err| {
+            io::Error::new(err.kind(), format!("--diff-against {}: not a directory or bundle, nor a git revision: {}", spec, err))
+        })
+    }
+
+    /// The text of the file at `relative` in the baseline, or `None` if the
+    /// baseline has no such file or it is not text.
+    fn text(&self, relative: &str, options: LoadOptions) -> io::Result<Option<String>> {
+        let dir = match self {
+            Baseline::Bundle(files) => return Ok(files.get(relative).cloned()),
+            Baseline::Dir(dir) => dir,
+            Baseline::Rev(snapshot) => &snapshot.dir,
+        };
+        let path = dir.join(relative);
+        if Path::new(relative).is_absolute() || !path.is_file() {
+            return Ok(None);
+        }
+        let options = LoadOptions { binary: BinaryPolicy::Skip, ..options };
+        match content::inspect(&path, String::new(), options)? {
+            Some(source) => source.body_text().map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// `source`, found at `relative` in the baseline, as a unified diff with
+    /// `context` lines around each change. Files that are new or not text are
+    /// kept whole; files whose text has not changed give `None`.
+    pub fn compare(&self, source: Source, relative: &str, context: usize, options: LoadOptions) -> io::Result<Option<Source>> {
+        if format::is_binary(source.encoding) {
+            return Ok(Some(source));
+        }
+        let Some(old) = self.text(relative, options)? else {
+            return Ok(Some(source));
+        };
+        let new = source.body_text()?;
+        if old == new {
+            return Ok(None);
+        }
+        let diff = TextDiff::from_lines(&old, &new).unified_diff().context_radius(context).to_string();
+        Ok(Some(source.into_diff(diff)))
+    }
+}
@@ -0,0 +1,344 @@
+use std::borrow::Cow;
+use std::path::{self, Component, Path};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::content::Source;
+use crate::template::{Field, Template};
+
+/// The layout of the bundle.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// Separator line with the path, followed by the file contents
+    #[default]
+    Plain,
+    /// A JSON array with one object per file
+    Json,
+    /// One JSON object per line
+    Jsonl,
+    /// An XML document with one <file> element per file
+    Xml,
+    /// A heading per file followed by a fenced code block
+    Markdown,
+}
+
+impl Format {
+    /// The bundle file extension used when `--dst-ext` is not given.
+    pub fn default_extension(self) -> &'static str {
+        match self {
+            Format::Plain => ".txt",
+            Format::Json => ".json",
+            Format::Jsonl => ".jsonl",
+            Format::Xml => ".xml",
+            Format::Markdown => ".md",
+        }
+    }
+
+    /// The separator used when `--file-sep` is not given, for a bundle with
+    /// this format and `extension`: `##` makes Markdown headers headings,
+    /// and `---` stands out in everything else.
+    pub fn default_file_sep(self, extension: &str) -> &'static str {
+        match self {
+            Format::Markdown => "##",
+            Format::Plain if matches!(extension, ".md" | ".markdown") => "##",
+            Format::Plain | Format::Json | Format::Jsonl | Format::Xml => "---",
+        }
+    }
+}
+
+/// How paths are shown in the bundle.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum PathStyle {
+    /// Relative to the source directory, e.g. src/main.rs
+    #[default]
+    Relative,
+    /// Prefixed with the source directory as given, e.g. ./src/main.rs
+    Prefixed,
+    /// Absolute, e.g. /home/me/project/src/main.rs
+    Absolute,
+}
+
+/// The path of `path` as shown in the bundle, with `/` as the separator on
+/// every platform. Files outside `src_dir` (from `--files-from`) keep their
+/// path as given when shown relative.
+pub fn label(path: &Path, src_dir: &Path, style: PathStyle) -> String {
+    let path = match style {
+        PathStyle::Relative => Cow::Borrowed(path.strip_prefix(src_dir).unwrap_or(path)),
+        PathStyle::Prefixed => Cow::Borrowed(path),
+        PathStyle::Absolute => Cow::Owned(path::absolute(path).unwrap_or_else(|_| path.to_path_buf())),
+    };
+    let mut label = String::new();
+    for component in path.components() {
+        match component {
+            Component::Prefix(prefix) => label.push_str(&prefix.as_os_str().to_string_lossy()),
+            Component::RootDir => label.push('/'),
+            Component::CurDir if style == PathStyle::Relative => continue,
+            Component::CurDir => label.push('.'),
+            Component::ParentDir => label.push_str(".."),
+            Component::Normal(name) => label.push_str(&name.to_string_lossy()),
+        }
+        if !matches!(component, Component::Prefix(_) | Component::RootDir) {
+            label.push('/');
+        }
+    }
+    if label.ends_with('/') {
+        label.pop();
+    }
+    label
+}
+
+/// The `encoding` of blocks whose contents are the file's own text.
+pub const TEXT: &str = "utf-8";
+
+/// The `encoding` of blocks holding a diff of the file against a baseline
+/// instead of its contents, see `--diff-against`.
+pub const DIFF: &str = "diff";
+
+/// Encodings of binary files that can follow the path in a plain or Markdown
+/// header. Text encodings such as `windows-1252` can follow it as well.
+const BINARY_ANNOTATIONS: &[&str] = &["base64", "hex", "binary"];
+
+/// Whether a block with this `encoding` holds a binary file rather than text.
+pub fn is_binary(encoding: &str) -> bool {
+    BINARY_ANNOTATIONS.contains(&encoding)
+}
+
+/// Renders blocks in a given format. A bundle (or each part of a split
+/// bundle) is `begin()`, then every block, then `end()`. A block is
+/// `block_start()`, the file contents passed through `content()` in as many
+/// pieces as needed, then `block_end()`.
+#[derive(Debug)]
+pub struct Renderer<'a> {
+    pub format: Format,
+    /// The `--file-sep`, which `{sep}` in the header and footer expands to.
+    pub file_sep: &'a Template,
+    /// The separator line of plain and Markdown blocks.
+    pub header: &'a Template,
+    /// A line after the contents of plain and Markdown blocks.
+    pub footer: Option<&'a Template>,
+}
+
+impl Renderer<'_> {
+    pub fn begin(&self) -> String {
+        match self.format {
+            Format::Json => "[".to_string(),
+            Format::Xml => "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<files>\n".to_string(),
+            Format::Plain | Format::Jsonl | Format::Markdown => String::new(),
+        }
+    }
+
+    /// The `--tree` overview, written after `begin()` of the first part. Formats
+    /// without a place for it (JSON and JSON Lines) return `None`.
+    pub fn tree(&self, tree: &str) -> Option<String> {
+        match self.format {
+            Format::Plain => Some(format!("{}\n", tree)),
+            Format::Markdown => {
+                let fence = "`".repeat(longest_backtick_run(tree).max(2) + 1);
+                Some(format!("{}text\n{}{}\n\n", fence, tree, fence))
+            }
+            Format::Xml => Some(format!("  <tree>{}</tree>\n", self.content(tree))),
+            Format::Json | Format::Jsonl => None,
+        }
+    }
+
+    /// Everything in front of the contents of `source`, shown as `label`;
+    /// `first` is true for the first block after `begin()`.
+    pub fn block_start(&self, source: &Source, label: &str, first: bool) -> String {
+        match self.format {
+            Format::Plain => format!("{}\n", self.fill(self.header, source, label)),
+            Format::Json => format!("{}\n  {}", if first { "" } else { "," }, json_start(source, label)),
+            Format::Jsonl => json_start(source, label),
+            Format::Xml => format!(
+                "  <file path=\"{}\" size=\"{}\" encoding=\"{}\">",
+                escape_attribute(label),
+                source.size,
+                source.encoding,
+            ),
+            Format::Markdown => format!(
+                "{}\n\n{}{}\n",
+                self.fill(self.header, source, label),
+                fence(source),
+                match source.encoding {
+                    DIFF => DIFF.to_string(),
+                    encoding if is_binary(encoding) => String::new(),
+                    _ => language(label),
+                },
+            ),
+        }
+    }
+
+    /// How `text` from the file's contents appears in the bundle.
+    pub fn content<'t>(&self, text: &'t str) -> Cow<'t, str> {
+        match self.format {
+            Format::Plain | Format::Markdown => Cow::Borrowed(text),
+            Format::Json | Format::Jsonl => {
+                let quoted = serde_json::to_string(text).expect("strings always serialize");
+                Cow::Owned(quoted[1..quoted.len() - 1].to_string())
+            }
+            Format::Xml if text.is_empty() => Cow::Borrowed(text),
+            // Every piece gets its own CDATA section, and "]]>" cannot appear
+            // inside one, so end the section in the middle of it. A parser
+            // would turn a carriage return in the section into a newline, so
+            // it goes between sections as a reference.
+            Format::Xml => {
+                let mut xml = String::with_capacity(text.len() + 12);
+                xml.push_str("<![CDATA[");
+                for c in text.replace("]]>", "]]]]><![CDATA[>").chars() {
+                    match c {
+                        '\r' => xml.push_str("]]>&#13;<![CDATA["),
+                        c => xml.push(xml_char(c)),
+                    }
+                }
+                xml.push_str("]]>");
+                Cow::Owned(xml)
+            }
+        }
+    }
+
+    /// Everything after the contents of `source`; `at_line_start` tells
+    /// whether the contents written so far were empty or ended in a newline.
+    pub fn block_end(&self, source: &Source, label: &str, at_line_start: bool) -> String {
+        let footer = match self.footer {
+            Some(footer) => format!("{}\n", self.fill(footer, source, label)),
+            None => String::new(),
+        };
+        match self.format {
+            Format::Plain => format!("\n{}", footer),
+            Format::Json => "\"}".to_string(),
+            Format::Jsonl => "\"}\n".to_string(),
+            Format::Xml => "</file>\n".to_string(),
+            Format::Markdown => format!("{}{}\n{}\n", if at_line_start { "" } else { "\n" }, fence(source), footer),
+        }
+    }
+
+    /// Whether blocks render differently depending on their `{index}` or `{count}`.
+    pub fn numbers_blocks(&self) -> bool {
+        let templates = [Some(self.header), self.footer];
+        let uses = |field| templates.iter().flatten().any(|template| template.uses_with(field, self.file_sep));
+        matches!(self.format, Format::Plain | Format::Markdown) && (uses(Field::Index) || uses(Field::Count))
+    }
+
+    /// A header or footer line for `source`, shown as `label`.
+    fn fill(&self, template: &Template, source: &Source, label: &str) -> String {
+        template.render(|field| match field {
+            Field::Sep => self.fill(self.file_sep, source, label),
+            Field::Path => header_path(label, source.encoding).into_owned(),
+            Field::Index => source.index.to_string(),
+            Field::Count => source.count.to_string(),
+            Field::Ext => Path::new(label).extension().map(|ext| ext.to_string_lossy().into_owned()).unwrap_or_default(),
+            Field::Lines => source.stats.lines.to_string(),
+            Field::Size => source.size.to_string(),
+            Field::Sha256 => source.sha256.clone().unwrap_or_default(),
+        })
+    }
+
+    pub fn end(&self) -> String {
+        match self.format {
+            Format::Json => "\n]\n".to_string(),
+            Format::Xml => "</files>\n".to_string(),
+            Format::Plain | Format::Jsonl | Format::Markdown => String::new(),
+        }
+    }
+}
+
+/// The path as shown in a plain or Markdown header: blocks that are not plain
+/// text get their encoding appended, e.g. `img/logo.png (base64)`.
+fn header_path<'a>(path: &'a str, encoding: &str) -> Cow<'a, str> {
+    if encoding == TEXT {
+        Cow::Borrowed(path)
+    } else {
+        Cow::Owned(format!("{} ({})", path, encoding))
+    }
+}
+
+/// Splits a header path written by `header_path` back into the path and its encoding.
+pub fn parse_header_path(header: &str) -> (&str, &str) {
+    if let Some((path, annotation)) = header.strip_suffix(')').and_then(|rest| rest.rsplit_once(" (")) {
+        if is_binary(annotation) || annotation == DIFF || encoding_rs::Encoding::for_label(annotation.as_bytes()).is_some() {
+            return (path, annotation);
+        }
+    }
+    (header, TEXT)
+}
+
+/// A JSON object for `source` up to the opening quote of its contents.
+fn json_start(source: &Source, label: &str) -> String {
+    let string = |value: &str| serde_json::to_string(value).expect("strings always serialize");
+    format!(
+        "{{\"path\":{},\"size\":{},\"encoding\":{},\"contents\":\"",
+        string(label),
+        source.size,
+        string(source.encoding),
+    )
+}
+
+/// A Markdown fence longer than any run of backticks in the contents.
+fn fence(source: &Source) -> String {
+    "`".repeat(source.stats.longest_backtick_run.max(2) + 1)
+}
+
+fn longest_backtick_run(text: &str) -> usize {
+    text.split(|c| c != '`').map(str::len).max().unwrap_or(0)
+}
+
+fn escape_attribute(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Parsers turn these into spaces unless they are references.
+            '\t' => escaped.push_str("&#9;"),
+            '\n' => escaped.push_str("&#10;"),
+            '\r' => escaped.push_str("&#13;"),
+            _ => escaped.push(xml_char(c)),
+        }
+    }
+    escaped
+}
+
+/// `c`, or U+FFFD if it is one of the control characters and noncharacters
+/// XML 1.0 has no place for, not even as a reference.
+fn xml_char(c: char) -> char {
+    match c {
+        '\0'..='\u{8}' | '\u{b}' | '\u{c}' | '\u{e}'..='\u{1f}' | '\u{fffe}' | '\u{ffff}' => char::REPLACEMENT_CHARACTER,
+        c => c,
+    }
+}
+
+/// The Markdown code block language for a path, derived from its extension.
+fn language(path: &str) -> String {
+    let extension = Path::new(path)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let language = match extension.as_str() {
+        "rs" => "rust",
+        "py" => "python",
+        "js" | "mjs" | "cjs" => "javascript",
+        "ts" => "typescript",
+        "jsx" => "jsx",
+        "tsx" => "tsx",
+        "c" | "h" => "c",
+        "cc" | "cpp" | "cxx" | "hpp" | "hh" => "cpp",
+        "cs" => "csharp",
+        "go" => "go",
+        "java" => "java",
+        "kt" | "kts" => "kotlin",
+        "rb" => "ruby",
+        "sh" | "bash" => "bash",
+        "ps1" => "powershell",
+        "md" => "markdown",
+        "yml" | "yaml" => "yaml",
+        "htm" | "html" => "html",
+        "txt" => "text",
+        other => other,
+    };
+    language.to_string()
+}
@@ -0,0 +1,207 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use crate::atomic;
+
+/// Runs git in `dir` and returns its standard output.
+fn git(dir: &Path, args: &[&str]) -> io::Result<Vec<u8>> {
+    let output = Command::new("git").arg("-C").arg(dir).args(args).output().map_err(|err| {
+        if err.kind() == io::ErrorKind::NotFound {
+            io::Error::new(err.kind(), "git selection needs the git command, which was not found")
+        } else {
+            err
+        }
+    })?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(format!("git {}: {}", args.join(" "), stderr.trim())));
+    }
+    Ok(output.stdout)
+}
+
+/// Splits the NUL-separated output of a git command given `-z` into paths.
+fn paths(output: Vec<u8>) -> io::Result<Vec<PathBuf>> {
+    let text = String::from_utf8(output)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "git listed a path that is not valid UTF-8"))?;
+    Ok(text.split('\0').filter(|path| !path.is_empty()).map(PathBuf::from).collect())
+}
+
+/// Files in the index, relative to `dir`.
+pub fn tracked(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    paths(git(dir, &["ls-files", "-z", "--cached"])?)
+}
+
+/// Files that differ from `HEAD`, staged or not, and untracked files that are
+/// not ignored, relative to `dir`. Deleted files are left out.
+pub fn changed(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    // In a repository without commits, everything in the index is a change.
+    let has_head = git(dir, &["rev-parse", "--verify", "--quiet", "HEAD"]).is_ok();
+    let mut files = if has_head {
+        paths(git(dir, &["diff", "--name-only", "-z", "--relative", "--diff-filter=d", "HEAD"])?)?
+    } else {
+        tracked(dir)?
+    };
+    files.extend(paths(git(dir, &["ls-files", "-z", "--others", "--exclude-standard"])?)?);
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+/// Files touched by `range` (e.g. `main..HEAD`, `main...feature` or a single
+/// revision to compare the working tree with), relative to `dir`. Files that
+/// the range deletes are left out.
+pub fn diff(dir: &Path, range: &str) -> io::Result<Vec<PathBuf>> {
+    paths(git(dir, &["diff", "--name-only", "-z", "--relative", "--diff-filter=d", "--end-of-options", range, "--"])?)
+}
+
+/// The files of a revision, read from the object database into a temporary
+/// directory so that they are bundled like files on disk. The directory is
+/// removed when the snapshot is dropped.
+#[derive(Debug)]
+pub struct Snapshot {
+    pub rev: String,
+    /// The temporary directory, standing in for the source directory.
+    pub dir: PathBuf,
+    /// Every file in the revision below the source directory, extracted or not.
+    pub all: Vec<PathBuf>,
+    /// The extracted files.
+    pub files: Vec<PathBuf>,
+}
+
+/// A file in the listing of `git ls-tree`.
+struct TreeEntry {
+    mode: String,
+    object: String,
+    path: PathBuf,
+}
+
+impl Snapshot {
+    /// Extracts the files of `rev` below `src_dir` for which `wanted` holds,
+    /// given their path relative to `src_dir`. Symbolic links and submodules are
+    /// left out, as they are when walking the source directory.
+    pub fn extract(src_dir: &Path, rev: &str, wanted: impl Fn(&Path) -> bool) -> io::Result<Self> {
+        if rev.starts_with('-') {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid revision: {}", rev)));
+        }
+        let entries = ls_tree(src_dir, rev)?;
+
+        // A run can hold more than one snapshot, e.g. with --git-rev and --diff-against.
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!("fbundle-rev-{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed)));
+        fs::create_dir_all(&dir)?;
+        atomic::register(dir.clone());
+        let mut snapshot = Snapshot { rev: rev.to_string(), dir, all: Vec::new(), files: Vec::new() };
+
+        let mut wanted_entries = Vec::new();
+        for entry in entries {
+            snapshot.all.push(snapshot.dir.join(&entry.path));
+            if wanted(&entry.path) {
+                wanted_entries.push(entry);
+            }
+        }
+        snapshot.write_blobs(src_dir, &wanted_entries)?;
+        Ok(snapshot)
+    }
+
+    /// Reads the blobs of `entries` with a single `git cat-file --batch`.
+    fn write_blobs(&mut self, src_dir: &Path, entries: &[TreeEntry]) -> io::Result<()> {
+        let mut child = Command::new("git")
+            .arg("-C")
+            .arg(src_dir)
+            .args(["cat-file", "--batch"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let objects: String = entries.iter().map(|entry| format!("{}\n", entry.object)).collect();
+        // Feed the object names from another thread, as git writes blobs while it reads names.
+        let feeder = thread::spawn(move || stdin.write_all(objects.as_bytes()));
+
+        let mut stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+        let result = self.read_blobs(&mut stdout, entries);
+        if result.is_err() {
+            // Left running, git could block on a full pipe and never exit.
+            let _ = child.kill();
+        }
+        drop(stdout);
+        let status = child.wait()?;
+        let fed = feeder.join().expect("feeding git cat-file does not panic");
+        result?;
+        fed?;
+        if !status.success() {
+            return Err(io::Error::other(format!("git cat-file --batch: {}", status)));
+        }
+        Ok(())
+    }
+
+    /// Copies the blobs of `entries`, as `git cat-file --batch` writes them to
+    /// `stdout`, into the snapshot.
+    fn read_blobs(&mut self, stdout: &mut impl BufRead, entries: &[TreeEntry]) -> io::Result<()> {
+        let mut header = String::new();
+        for entry in entries {
+            header.clear();
+            stdout.read_line(&mut header)?;
+            let size = match header.trim_end().split(' ').collect::<Vec<_>>()[..] {
+                [_, "blob", size] => size.parse::<u64>().map_err(io::Error::other)?,
+                _ => return Err(io::Error::other(format!("git cat-file: unexpected reply {:?}", header.trim_end()))),
+            };
+            let path = self.dir.join(&entry.path);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut file = File::create(&path)?;
+            io::copy(&mut stdout.take(size), &mut file)?;
+            set_mode(&file, &entry.mode)?;
+            stdout.read_exact(&mut [0])?;
+            self.files.push(path);
+        }
+        Ok(())
+    }
+
+    /// Where `path`, a file of the snapshot, is in the working tree.
+    pub fn working_path(&self, path: &Path, src_dir: &Path) -> PathBuf {
+        match path.strip_prefix(&self.dir) {
+            Ok(relative) => src_dir.join(relative),
+            Err(_) => path.to_path_buf(),
+        }
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+        atomic::unregister(&self.dir);
+    }
+}
+
+/// Lists the regular files of `rev` below `dir`, relative to `dir`.
+fn ls_tree(dir: &Path, rev: &str) -> io::Result<Vec<TreeEntry>> {
+    let output = git(dir, &["ls-tree", "-r", "-z", rev])?;
+    let text = String::from_utf8(output)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "git listed a path that is not valid UTF-8"))?;
+    let mut entries = Vec::new();
+    for line in text.split('\0').filter(|line| !line.is_empty()) {
+        // <mode> SP <type> SP <object> TAB <path>
+        let (info, path) = line.split_once('\t').ok_or_else(|| io::Error::other(format!("git ls-tree: unexpected line {:?}", line)))?;
+        if let [mode @ ("100644" | "100755"), "blob", object] = info.split(' ').collect::<Vec<_>>()[..] {
+            entries.push(TreeEntry { mode: mode.to_string(), object: object.to_string(), path: PathBuf::from(path) });
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(unix)]
+fn set_mode(file: &File, mode: &str) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = if mode == "100755" { 0o755 } else { 0o644 };
+    file.set_permissions(fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_file: &File, _mode: &str) -> io::Result<()> {
+    Ok(())
+}
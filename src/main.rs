@@ -1,34 +1,141 @@
-use std::fs::File;
-use std::io::{self, BufWriter, Read, Write};
-use std::path::PathBuf;
-use std::sync::Mutex;
-
-use clap::Parser;
-use ignore::overrides::OverrideBuilder;
-use ignore::WalkBuilder;
+/// Prints a status message. When the bundle itself goes to stdout, status
+/// messages go to stderr instead.
+macro_rules! status {
+    ($args:expr, $($arg:tt)*) => {
+        if $args.to_stdout() {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
+mod apply;
+mod atomic;
+mod cache;
+mod config;
+mod collision;
+mod content;
+mod diff;
+mod format;
+mod git;
+mod manifest;
+mod order;
+mod output;
+mod preset;
+mod select;
+mod template;
+mod tokens;
+mod tree;
+mod unbundle;
+mod verify;
+mod watch;
+
+use std::collections::HashSet;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
 use serde::Deserialize;
 use rayon::prelude::*;
 
+use crate::cache::{Cache, Stamp};
+use crate::collision::{Markers, SepCollision};
+use crate::content::{BinaryPolicy, LoadOptions, Source};
+use crate::diff::Baseline;
+use crate::format::{Format, PathStyle, Renderer};
+use crate::manifest::{Manifest, ManifestMode, Selection};
+use crate::select::WalkOptions;
+use crate::template::{Field, Template};
+use crate::order::SortOrder;
+use crate::output::{Limits, Output, OutputFiles, Overflow};
+use crate::tokens::Tokenizer;
+use crate::tree::TreeInfo;
+
 #[derive(Debug, Deserialize, Parser)]
 #[command(
     author,
     version,
     about,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
     long_about = "
 FileBundle - A utility for bundling multiple files into a single output file.
 
 USAGE:
-    fbundle [OPTIONS] -f <FILE_SEPERATOR> -g <GLOB_PATTERN>...
+    fbundle [OPTIONS] [-f <FILE_SEPERATOR>] -g <GLOB_PATTERN>...
+    fbundle unbundle [OPTIONS] [-f <FILE_SEPERATOR>] <BUNDLE>...
+    fbundle verify [OPTIONS] <BUNDLE>...
+    fbundle apply [OPTIONS] [-f <FILE_SEPERATOR>] <BUNDLE>...
+    fbundle cache clean
+    fbundle presets
 
 OPTIONS:
     -n, --bundle-name <NAME>    Set the name of the output bundle file (default: 'file_bundle')
     -s, --src-dir <DIR>         Specify the source directory to search for files (default: current directory)
     -o, --out-dir <DIR>         Set the output directory for the bundle file (default: current directory)
-    -e, --dst-ext <EXT>         Set the file extension for the output bundle file (default: '.txt')
+                                Use '-' to write the bundle to stdout
+    -e, --dst-ext <EXT>         Set the file extension for the output bundle file
+                                (default: '.txt', or '.json', '.jsonl', '.xml', '.md' to match --format)
     -f, --file-sep <SEP>        Specify a custom separator string to use between files in the bundle
+                                Understands \\n, \\t, \\r, \\\\, \\xNN and \\u{...}, and the placeholders
+                                {path}, {index}, {count}, {ext}, {lines}, {size} and {sha256}
+                                (default: '##' for Markdown and .md bundles, '---' otherwise)
+    --preset <NAME>             Use a built-in layout for the options among --format, --file-sep,
+                                --header-template and --footer-template not given; see 'presets'
     -g, --src-globs <PATTERNS>  Provide one or more glob patterns to match source files
                                 Use '!' prefix for exclusion patterns
                                 Can be specified multiple times for multiple patterns
+    --files-from <FILE>         Bundle the files listed in FILE ('-' for stdin) instead of walking
+                                the source directory. Paths are separated by newlines or NUL bytes
+    --git-tracked               Only bundle files in the git index
+    --git-changed               Only bundle files that differ from HEAD, staged or not, and untracked
+                                files that git does not ignore
+    --git-diff <REV_RANGE>      Only bundle files changed in a revision range, e.g. 'main...HEAD', or
+                                between a revision and the working tree
+    --git-rev <REV>             Bundle the files as they are in a revision, e.g. 'v1.2.0', read from
+                                the git object database without checking it out
+    --diff-against <BASELINE>   Bundle a unified diff of each file against a directory, an earlier
+                                bundle or a git revision instead of its contents
+    --diff-context <LINES>      Lines of context around each change in a diff [default: 3]
+    --respect-gitignore         Skip files ignored by .gitignore, .git/info/exclude and the global git excludes
+    --respect-ignore-files      Skip files ignored by .ignore files
+    --custom-ignore-file <NAME> Also read ignore rules from files with this name, e.g. '.fbundleignore'
+                                Can be specified multiple times
+    --sort <ORDER>              Order of files in the bundle: path, walk, size or mtime (default: path)
+    --priority <PATTERN>        Put files matching the pattern first, in the order the patterns are given
+                                Can be specified multiple times
+    --binary <POLICY>           What to do with binary files: skip, placeholder, base64 or hex
+                                (default: placeholder)
+    --input-encoding <LABEL>    Read text that is not UTF-8 in this encoding instead of guessing,
+                                e.g. 'windows-1252', 'latin1', 'shift_jis' or 'utf-16le'
+    --lossy                     Keep text that does not decode cleanly, replacing the bad bytes
+    --format <FORMAT>           Layout of the bundle: plain, json, jsonl, xml or markdown (default: plain)
+    --max-bytes <BYTES>         Split the bundle into numbered parts of at most this many bytes
+    --max-tokens <TOKENS>       Limit the bundle to this many tokens; see --overflow
+    --overflow <ACTION>         What --max-tokens does with a larger bundle: split it into numbered
+                                parts, fail, or drop the lowest-priority files (default: split)
+    --tokenizer <TOKENIZER>     How tokens are counted: chars (one token per four characters),
+                                cl100k or o200k (default: chars)
+    --count-tokens              Report the number of tokens of each file and of the whole bundle
+    --path-style <STYLE>        How paths are shown: relative to the source directory, prefixed with
+                                it as given, or absolute (default: relative)
+    --sep-auto                  Lengthen the separator until no line of any file collides with it
+    --sep-collision <MODE>      What to do with lines that would be read back as a file header or
+                                footer: warn, fail or escape (default: warn)
+    --header-template <TEXT>    The separator line of each file (default: '{sep} {path}')
+    --footer-template <TEXT>    A line written after the contents of each file
+    --manifest <MODE>           Record the size, SHA-256, mode and mtime of every file in a sidecar
+                                <NAME>.manifest.json or embedded at the end of the bundle
+    --tree                      Start the bundle with a directory tree of the files in it
+    --tree-info <INFO>          Show size, lines and/or tokens next to each file in the tree,
+                                e.g. --tree-info size,tokens
+    --tree-excluded             Also show files in the source directory that are not in the bundle
+    --watch                     Keep running and rewrite the bundle whenever matching files change
+    --no-cache                  Read every file again instead of reusing what earlier runs cached
+    -p, --profile <NAME>        Use the settings of [profile.NAME] from fbundle.toml
     -v, --verbose               Enable verbose output
 
 DESCRIPTION:
@@ -37,7 +144,145 @@ DESCRIPTION:
     their contents, and writes them to the output file.
 
     Files are separated in the output by the specified separator string, followed by the
-    file's path relative to the source directory.
+    file's path relative to the source directory, with '/' between directories on every
+    platform. Files are read in parallel but always written in a fixed order, so the same
+    input produces a byte-identical bundle.
+
+    The bundle, its parts, index and sidecar manifest are written to temporary files in
+    the output directory, flushed to disk and renamed into place only once all of them are
+    complete. A run that fails or is interrupted with Ctrl-C removes its temporary files
+    and leaves the previous bundle as it was. When the output directory is inside the
+    source directory, the files of the bundle (the bundle, parts of an earlier split, the
+    index, the sidecar manifest and temporary files) are never bundled, even if the globs
+    match them; -v lists them.
+
+    With --max-bytes, or --max-tokens and the default '--overflow split', the bundle is
+    written as file_bundle.001.txt, file_bundle.002.txt, ... and file_bundle.index.txt
+    lists the part each file went into. A file is only split if it does not fit into a
+    part on its own; its later pieces get headers ending in '(continued)', which
+    'unbundle' joins back together.
+
+    Text that is not UTF-8 is transcoded: UTF-16 files are recognised by their byte order
+    mark, and other encodings such as Windows-1252 or Shift_JIS are guessed unless
+    --input-encoding names one. The original encoding follows the header path, e.g.
+    '--- legacy/main.c (windows-1252)', and 'unbundle' converts the file back to it.
+    Text that does not decode cleanly is treated as binary, unless --lossy is given.
+
+    A file is treated as binary if its first 8 KiB contain a NUL byte or mostly control
+    characters, or if it cannot be decoded as text. Binary files are left out with
+    --binary skip, replaced by a line with their size and SHA-256 hash with 'placeholder',
+    or embedded with 'base64' or 'hex', which 'unbundle' decodes back into the original
+    bytes. Their header path is followed by the encoding, e.g. '--- img/logo.png (base64)'.
+
+    With '-o -' the bundle is written to stdout and status messages go to stderr; it
+    cannot be split, so it cannot be combined with --max-bytes or '--overflow split'.
+    --files-from replaces the walk of the source directory with an explicit list; the
+    globs still filter the list, ignore files are not consulted, and '--sort walk' keeps
+    the order of the list.
+
+    --git-tracked, --git-changed and --git-diff select files with git instead of walking
+    the source directory, which has to be inside a git work tree. When several are given,
+    only files picked by all of them are bundled, and only those matching the globs (use
+    -g '*' for all). Files deleted by a change are left out.
+
+    --git-rev bundles the files of a revision instead of the working tree, read from the
+    object database without touching the checkout. The globs match paths in the revision,
+    any other git selection narrows them down, and each header is labelled with the
+    revision, e.g. '--- v1.2.0:src/main.rs'. It cannot be combined with --watch.
+    'unbundle' and 'apply' take the revision off the paths again: the one the manifest
+    records, or the one given with --git-rev.
+
+    --diff-against replaces the contents of each file with a unified diff against its
+    version in a baseline: a directory with the same layout, an earlier plain bundle made
+    with the same --file-sep and templates, or a git revision. Diffs have --diff-context
+    lines of context and are marked in the header, e.g. '--- src/main.rs (diff)'. Files
+    the baseline lacks, and binary files, are bundled whole; unchanged files are left out.
+    'unbundle' skips diffs.
+
+    Settings can be kept in an fbundle.toml in the source directory or one of its parents.
+    Top-level keys are defaults for every run and [profile.NAME] tables override them
+    when selected with --profile NAME. Keys are named like the options, with dashes
+    replaced by underscores (file_sep, src_globs, out_dir, format, ...). Options given on
+    the command line override the config, and relative paths in the config are relative
+    to the directory that holds it.
+
+    Without --file-sep, the separator is '##' for Markdown bundles and plain bundles with
+    a .md extension, which makes every header a heading, and '---' otherwise. 'unbundle'
+    and 'apply' read .md bundles as Markdown with '##', and others as plain with '---',
+    unless --format or -f says otherwise. --preset NAME selects a built-in layout, listed
+    with 'fbundle presets': claude-xml, markdown-fence, gpt-plain or rfc822-like. A
+    preset fills in --format, --file-sep, --header-template and --footer-template where
+    neither the command line nor the config sets them, and 'unbundle --preset' and
+    'apply --preset' read such a bundle back.
+
+    Tokens are counted with --tokenizer: 'chars' is a fast estimate, while 'cl100k' and
+    'o200k' are the BPE vocabularies used by OpenAI models, built into fbundle. Counts
+    include the separator lines and any markup of the chosen format. With
+    '--overflow fail', a bundle over --max-tokens is not written at all; with
+    '--overflow drop', files are left out from the end of the bundle, which holds the
+    files of the lowest priority (see --priority and --sort), until it fits.
+
+    --header-template and --footer-template shape the line in front of and after the
+    contents of each file in plain and Markdown bundles. They may contain {sep}, {path},
+    {lines}, {size} (in bytes), {sha256}, {index} and {count} (the position of the file
+    in the bundle, from 1, and the number of files) and {ext} (its extension); write {{
+    and }} for literal braces. {path} includes the encoding annotation of files that are
+    not UTF-8 text. Pass the same templates to 'unbundle' to read such a bundle back.
+
+    --file-sep may contain the same placeholders, except {sep}, and the escapes \\n, \\t,
+    \\r, \\0, \\\\, \\xNN (an ASCII character) and \\u{...} (any character, e.g. \\u{1F4C4}).
+    A separator with newlines makes headers and footers span several lines, which
+    'unbundle' reads back as long as it is given the same separator.
+
+    A line of a file that matches the header or footer template, e.g. '--- notes' with
+    '-f ---', would be read back as the start of another file. Plain bundles are checked
+    for such lines: --sep-collision warns about them by default, 'fail' writes nothing,
+    and 'escape' puts a backslash in front of them (and of lines that already look
+    escaped), which 'unbundle --unescape' and 'apply --unescape' remove. --sep-auto
+    instead repeats the last non-blank character of the separator until no line collides
+    and reports the separator it chose. Markdown bundles need neither, as reading one
+    back skips over the fenced code blocks.
+
+    --manifest records what the bundle was made from: the globs and ignore settings, and
+    the size, SHA-256, permissions and modification time of every selected file. 'verify'
+    reads the manifest of a bundle (from the sidecar, or from the bundle with 'embedded')
+    and lists the files in the source directory that were added, removed or modified
+    since, exiting with status 1 if there are any. Modification times are recorded but not
+    compared.
+
+    'apply' writes an edited bundle back to the source directory: it prints a unified diff
+    of every file that differs, then writes only those, together. With a manifest, files
+    that changed in the source directory since the bundle was created are conflicts, and
+    nothing is written unless --force is given; --report-deleted lists the files that were
+    removed from the bundle. Files are never deleted. --dry-run only shows the changes.
+    'unbundle' and 'apply' read plain bundles, and Markdown bundles (those ending in .md,
+    or with --format markdown), taking the fenced code blocks apart again. Markdown does
+    not record whether a file ended in a newline: 'unbundle' writes every such file with
+    one, and 'apply' keeps the ending the file in the source directory has.
+
+    --watch writes the bundle, then keeps watching the source directory and rewrites it
+    shortly after files that match the globs (or the ignore files in use) change.
+    fbundle.toml is read once at start.
+
+    Files are cached between runs: what was read from a file, transcoded or encoded, its
+    hash and its token counts are kept in $FBUNDLE_CACHE_DIR, or in fbundle in the user's
+    cache directory (e.g. ~/.cache/fbundle), and reused as long as the file's size,
+    modification time and inode are unchanged. --no-cache reads every file again, and
+    'fbundle cache clean' removes the cache's entries (and the directory, if that leaves
+    it empty).
+
+    --tree puts an ASCII directory tree of the bundled files in front of the first file
+    (in plain, Markdown and XML bundles; split bundles have it in the first part only).
+    With --tree-excluded, files that the ignore rules let through but that are not in the
+    bundle, because they do not match the globs, are binary or were dropped, are marked
+    '[excluded]'; a directory with nothing in the bundle is shown as a single line.
+
+    --format selects the layout. 'plain' writes the separator line described above.
+    'json' and 'jsonl' write objects with the path, size, encoding and contents of each
+    file, 'xml' writes <file path=\"...\"> elements with the contents in CDATA sections,
+    and 'markdown' writes the separator line followed by a fenced code block tagged with
+    a language derived from the file extension. Split parts are complete documents.
+    Control characters XML 1.0 does not allow, such as form feeds, become U+FFFD in XML.
 
 EXAMPLES:
     1. Bundle all .txt files in the current directory:
@@ -52,11 +297,102 @@ EXAMPLES:
     4. Bundle files with multiple include and exclude patterns:
        fbundle -f '---' -g '**/*.{js,ts}' -g '!**/node_modules/**' -g '!**/dist/**'
 
+    5. Recreate the files of a bundle in ./restored, previewing first:
+       fbundle unbundle -f '---' file_bundle.txt -o ./restored --dry-run
+       fbundle unbundle -f '---' file_bundle.txt -o ./restored
+
+    6. Put the README and entry point first, then the rest by size:
+       fbundle -f '---' -g '**/*.rs' -g 'README.md' --priority 'README.md' --priority 'src/main.rs' --sort size
+
+    7. Split a bundle into parts that fit a 100k-token context window, then restore it:
+       fbundle -f '---' -g '**/*.rs' --max-tokens 100000
+       fbundle unbundle -f '---' file_bundle.0*.txt -o ./restored
+
+    8. Bundle sources as JSON Lines for programmatic use, or as Markdown for reading:
+       fbundle -f '' -g '**/*.rs' --format jsonl
+       fbundle -f '##' -g '**/*.rs' --format markdown
+
+    9. Bundle a repository, skipping everything git ignores and anything listed in .fbundleignore:
+       fbundle -f '---' -g '*' --respect-gitignore --custom-ignore-file .fbundleignore
+
+    10. Bundle a web project including its images, so it can be unbundled losslessly:
+       fbundle -f '---' -g 'src/**' -g 'assets/**' --binary base64
+
+    11. Bundle the Rust files git knows about and pipe the bundle to another tool:
+       git ls-files -z | fbundle -f '---' -g '*.rs' --files-from - -o - | wc -c
+
+    12. With an fbundle.toml containing:
+           file_sep = '---'
+           src_globs = ['**/*.rs', '**/*.toml']
+           [profile.backend]
+           src_globs = ['server/**']
+           bundle_name = 'backend'
+       bundle everything, or just the backend as Markdown:
+       fbundle
+       fbundle --profile backend --format markdown
+
+    13. Check how much of a 128k-token context the sources need, keeping the most important
+       files if they do not all fit:
+       fbundle -f '---' -g '**/*.rs' --tokenizer o200k --count-tokens
+       fbundle -f '---' -g '**/*.rs' --tokenizer o200k --max-tokens 128000 --overflow drop --priority 'src/main.rs'
+
+    14. Start the bundle with an overview of what is in it and what was left out:
+       fbundle -f '---' -g 'src/**' --respect-gitignore --tree --tree-info size,lines --tree-excluded
+
+    15. Show line counts and checksums in the headers, mark the end of each file, and restore it:
+       fbundle -f '---' -g '**/*.rs' --header-template '{sep} {path} ({lines} lines, {sha256})' --footer-template '{sep} end'
+       fbundle unbundle -f '---' file_bundle.txt -o ./restored --header-template '{sep} {path} ({lines} lines, {sha256})' --footer-template '{sep} end'
+
+    16. Archive a bundle with its manifest, and later check the source tree against it:
+       fbundle -f '---' -g '**/*.rs' --manifest sidecar
+       fbundle verify file_bundle.txt --src-dir .
+
+    17. Keep a Markdown bundle of the sources up to date while editing:
+       fbundle -f '##' -g 'src/**/*.rs' --format markdown --watch
+
+    18. Bundle the files changed on a branch for review, or just the uncommitted ones:
+       fbundle -f '---' -g '*' --git-diff main...HEAD --format markdown
+       fbundle -f '---' -g '**/*.rs' --git-changed
+
+    19. Bundle the sources of a release, or just the files a branch changed as they were before it:
+       fbundle -f '---' -g 'src/**/*.rs' --git-rev v1.2.0
+       fbundle -f '---' -g '*' --git-rev main --git-diff main...HEAD
+
+    20. Review what changed since a release, or since the last bundle, hunk by hunk:
+       fbundle -f '---' -g 'src/**' --diff-against v1.2.0 --format markdown
+       fbundle -f '---' -g 'src/**' --diff-against file_bundle.txt --diff-context 10
+
+    21. Hand a bundle out for editing, then review and write back what came back:
+       fbundle -f '---' -g 'src/**/*.rs' --manifest embedded
+       fbundle apply -f '---' file_bundle.txt --dry-run --report-deleted
+       fbundle apply -f '---' file_bundle.txt
+
+    22. Bundle Markdown notes full of '---' rules without confusing 'unbundle':
+       fbundle -f '---' -g 'docs/**/*.md' --sep-auto
+       fbundle -f '---' -g 'docs/**/*.md' --sep-collision escape
+       fbundle unbundle -f '---' file_bundle.txt -o ./restored --unescape
+
+    23. Number the files in a multi-line separator, and read the bundle back:
+       fbundle -f '\\n<<<{index}/{count}: {path}>>>' --header-template '{sep}' -g 'src/**/*.rs'
+       fbundle unbundle -f '\\n<<<{index}/{count}: {path}>>>' --header-template '{sep}' file_bundle.txt -o ./restored
+
+    24. Bundle for a chat model with a built-in layout, and read the answer back:
+       fbundle presets
+       fbundle -g 'src/**/*.rs' --preset claude-xml
+       fbundle unbundle --preset claude-xml file_bundle.txt -o ./restored
+
 NOTE:
+    Ignore files are not read unless asked for with --respect-gitignore,
+    --respect-ignore-files or --custom-ignore-file. When enabled, nested ignore files apply
+    to their own subtree, and .gitignore rules apply even outside a git repository.
     Glob patterns are case-insensitive by default. The tool uses the 'ignore' crate for
     efficient file traversal and the 'glob' crate for pattern matching."
 )]
 struct FileBundle {
+    #[command(subcommand)]
+    #[serde(skip)]
+    command: Option<Command>,
+
     #[arg(short = 'n', long, default_value = "file_bundle")]
     bundle_name: String,
 
@@ -66,81 +402,647 @@ struct FileBundle {
     #[arg(short = 'o', long, default_value = ".")]
     out_dir: PathBuf,
 
-    #[arg(short = 'e', long, default_value = ".txt")]
-    dst_ext: String,
+    #[arg(short = 'e', long)]
+    #[serde(default)]
+    dst_ext: Option<String>,
+
+    #[arg(short = 'f', long, allow_hyphen_values = true)]
+    #[serde(default)]
+    file_sep: Option<String>,
 
-    #[arg(short = 'f', long)]
-    file_sep: String,
+    #[arg(long)]
+    #[serde(default)]
+    preset: Option<String>,
 
     #[arg(short = 'g', long)]
     src_globs: Vec<String>,
 
+    #[arg(long)]
+    #[serde(default)]
+    files_from: Option<PathBuf>,
+
+    #[arg(long, default_value_t = false)]
+    #[serde(default)]
+    git_tracked: bool,
+
+    #[arg(long, default_value_t = false)]
+    #[serde(default)]
+    git_changed: bool,
+
+    #[arg(long, value_name = "REV_RANGE", allow_hyphen_values = true)]
+    #[serde(default)]
+    git_diff: Option<String>,
+
+    #[arg(long, value_name = "REV")]
+    #[serde(default)]
+    git_rev: Option<String>,
+
+    #[arg(long, value_name = "BASELINE")]
+    #[serde(default)]
+    diff_against: Option<String>,
+
+    #[arg(long, value_name = "LINES", default_value_t = default_diff_context())]
+    #[serde(default = "default_diff_context")]
+    diff_context: usize,
+
+    #[arg(long, default_value_t = false)]
+    #[serde(default)]
+    respect_gitignore: bool,
+
+    #[arg(long, default_value_t = false)]
+    #[serde(default)]
+    respect_ignore_files: bool,
+
+    #[arg(long)]
+    #[serde(default)]
+    custom_ignore_file: Vec<String>,
+
+    #[arg(long, value_enum, default_value_t = SortOrder::Path)]
+    #[serde(default)]
+    sort: SortOrder,
+
+    #[arg(long)]
+    #[serde(default)]
+    priority: Vec<String>,
+
+    #[arg(long, value_enum, default_value_t = BinaryPolicy::Placeholder)]
+    #[serde(default)]
+    binary: BinaryPolicy,
+
+    #[arg(long)]
+    #[serde(default)]
+    input_encoding: Option<String>,
+
+    #[arg(long, default_value_t = false)]
+    #[serde(default)]
+    lossy: bool,
+
+    #[arg(long, value_enum, default_value_t = Format::Plain)]
+    #[serde(default)]
+    format: Format,
+
+    #[arg(long)]
+    #[serde(default)]
+    max_bytes: Option<usize>,
+
+    #[arg(long)]
+    #[serde(default)]
+    max_tokens: Option<usize>,
+
+    #[arg(long, value_enum, default_value_t = Overflow::Split)]
+    #[serde(default)]
+    overflow: Overflow,
+
+    #[arg(long, value_enum, default_value_t = Tokenizer::Chars)]
+    #[serde(default)]
+    tokenizer: Tokenizer,
+
+    #[arg(long, default_value_t = false)]
+    #[serde(default)]
+    count_tokens: bool,
+
+    #[arg(long, value_enum, default_value_t = PathStyle::Relative)]
+    #[serde(default)]
+    path_style: PathStyle,
+
+    #[arg(long, default_value_t = false)]
+    #[serde(default)]
+    sep_auto: bool,
+
+    #[arg(long, value_enum, default_value_t = SepCollision::Warn)]
+    #[serde(default)]
+    sep_collision: SepCollision,
+
+    #[arg(long, allow_hyphen_values = true)]
+    #[serde(default)]
+    header_template: Option<String>,
+
+    #[arg(long, allow_hyphen_values = true)]
+    #[serde(default)]
+    footer_template: Option<String>,
+
+    #[arg(long, value_enum)]
+    #[serde(default)]
+    manifest: Option<ManifestMode>,
+
+    #[arg(long, default_value_t = false)]
+    #[serde(default)]
+    tree: bool,
+
+    #[arg(long, value_enum, value_delimiter = ',')]
+    #[serde(default)]
+    tree_info: Vec<TreeInfo>,
+
+    #[arg(long, default_value_t = false)]
+    #[serde(default)]
+    tree_excluded: bool,
+
+    #[arg(long, default_value_t = false)]
+    #[serde(default)]
+    no_cache: bool,
+
+    #[arg(long, default_value_t = false)]
+    #[serde(default)]
+    watch: bool,
+
     #[arg(short = 'v', long, default_value_t = false)]
     verbose: bool,
+
+    #[arg(short = 'p', long)]
+    #[serde(skip)]
+    profile: Option<String>,
+}
+
+fn default_diff_context() -> usize {
+    3
+}
+
+impl FileBundle {
+    fn to_stdout(&self) -> bool {
+        self.out_dir.as_os_str() == "-"
+    }
+
+    fn extension(&self) -> &str {
+        self.dst_ext.as_deref().unwrap_or(self.format.default_extension())
+    }
+
+    /// Whether the files are selected by git rather than by walking the source directory.
+    fn uses_git(&self) -> bool {
+        self.git_tracked || self.git_changed || self.git_diff.is_some()
+    }
+
+    /// A token budget that is not met by splitting, so the bundle has to be counted up front.
+    fn budget(&self) -> Option<usize> {
+        self.max_tokens.filter(|_| self.overflow != Overflow::Split)
+    }
+
+    /// A token limit for each part of a split bundle.
+    fn split_tokens(&self) -> Option<usize> {
+        self.max_tokens.filter(|_| self.overflow == Overflow::Split)
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Recreate the original file tree from a bundle
+    Unbundle(unbundle::UnbundleArgs),
+    /// Check a source tree against the manifest of a bundle
+    Verify(verify::VerifyArgs),
+    /// Write the files changed in an edited bundle back to the source tree
+    Apply(apply::ApplyArgs),
+    /// Manage the cache of files read by earlier runs
+    Cache(cache::CacheArgs),
+    /// List the built-in layouts that --preset selects
+    Presets,
 }
 
 fn main() -> io::Result<()> {
-    let mut args = FileBundle::parse();
-    
-    args.file_sep = args.file_sep.replace("\\n", "\n");
+    let matches = FileBundle::command().get_matches();
+    let mut args = FileBundle::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
 
-    let out_path = args.out_dir.join(format!("{}{}", args.bundle_name, args.dst_ext));
-    let writer = Mutex::new(BufWriter::new(File::create(&out_path)?));
-    
-    if args.verbose {
-        println!("Glob patterns: {:?}", args.src_globs);
+    match args.command.take() {
+        Some(Command::Unbundle(unbundle_args)) => return unbundle::run(unbundle_args),
+        Some(Command::Verify(verify_args)) => return verify::run(verify_args),
+        Some(Command::Apply(apply_args)) => return apply::run(apply_args),
+        Some(Command::Cache(cache_args)) => return cache::run(cache_args),
+        Some(Command::Presets) => return preset::run(),
+        None => {}
+    }
+
+    let from_config = config::apply(&mut args, &matches)?;
+    if let Some(name) = &args.preset {
+        // A preset fills in what neither the command line nor the config set.
+        let preset = preset::find(name)?;
+        if matches.value_source("format") != Some(ValueSource::CommandLine) && !from_config.contains(&"format") {
+            args.format = preset.format;
+        }
+        args.file_sep.get_or_insert_with(|| preset.file_sep.to_string());
+        args.header_template.get_or_insert_with(|| preset.header_template.to_string());
+        if let Some(footer) = preset.footer_template {
+            args.footer_template.get_or_insert_with(|| footer.to_string());
+        }
+    }
+
+    let file_sep = match args.file_sep.take() {
+        Some(file_sep) => file_sep,
+        None => args.format.default_file_sep(args.extension()).to_string(),
+    };
+    let file_sep = Template::separator(&file_sep)?;
+
+    if args.to_stdout() && (args.max_bytes.is_some() || args.split_tokens().is_some()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "a bundle written to stdout cannot be split: use --overflow fail or drop with --max-tokens",
+        ));
+    }
+
+    if args.to_stdout() && args.manifest == Some(ManifestMode::Sidecar) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "a bundle written to stdout has no place for a sidecar manifest: use --manifest embedded",
+        ));
+    }
+
+    if args.files_from.is_some() && (args.uses_git() || args.git_rev.is_some()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--files-from cannot be combined with --git-tracked, --git-changed, --git-diff or --git-rev",
+        ));
+    }
+
+    if args.tree && matches!(args.format, Format::Json | Format::Jsonl) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--tree is not supported with --format json or jsonl",
+        ));
+    }
+
+    atomic::remove_temporaries_on_interrupt()?;
+
+    if args.watch {
+        if args.git_rev.is_some() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "--watch cannot be combined with --git-rev, whose files never change"));
+        }
+        if args.to_stdout() || args.files_from.as_deref() == Some(Path::new("-")) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--watch needs a bundle file and a source directory to watch: it cannot be combined with '-o -' or '--files-from -'",
+            ));
+        }
+        return watch::run(&args, &file_sep);
     }
 
-    let mut override_builder = OverrideBuilder::new(&args.src_dir);
-    for pattern in &args.src_globs {
-        override_builder.add(pattern).map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))?;
+    bundle(&args, &file_sep)
+}
+
+/// Selects, reads and writes the files of one bundle.
+fn bundle(args: &FileBundle, file_sep: &Template) -> io::Result<()> {
+    let budget = args.budget();
+    if args.verbose {
+        status!(args, "Glob patterns: {:?}", args.src_globs);
     }
-    let overrides = override_builder.build().map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))?;
 
-    let walker = WalkBuilder::new(&args.src_dir)
-        .hidden(false)
-        .ignore(false)
-        .git_ignore(false)
-        .overrides(overrides)
-        .build();
-    
-    let files: Vec<_> = walker
-        .filter_map(Result::ok)
-        .filter(|entry| entry.file_type().map_or(false, |ft| ft.is_file()))
-        .collect();
+    let overrides = select::overrides(WalkOptions::of(args))?;
+    // With --git-rev, the files are read from a snapshot of the revision that stands in for the source directory.
+    let snapshot = args.git_rev.as_deref().map(|rev| select::git_snapshot(args, rev, &overrides)).transpose()?;
+    let root = snapshot.as_ref().map_or(args.src_dir.as_path(), |snapshot| snapshot.dir.as_path());
+    let mut files = match (&snapshot, &args.files_from) {
+        (Some(snapshot), _) => snapshot.files.clone(),
+        (None, Some(list)) => select::files_from(args, list, &overrides)?,
+        (None, None) if args.uses_git() => select::git_files(args, &overrides)?,
+        (None, None) => select::walk(WalkOptions::of(args), overrides),
+    };
+    // Files of a snapshot are shown as `<rev>:<path>` with the path they have in the source directory.
+    let label = |path: &Path, style: PathStyle| match &snapshot {
+        Some(snapshot) => {
+            let path = snapshot.working_path(path, &args.src_dir);
+            format!("{}:{}", snapshot.rev, format::label(&path, &args.src_dir, style))
+        }
+        None => format::label(path, &args.src_dir, style),
+    };
+    // Where a file is in the source directory, for the manifest and the --diff-against baseline.
+    let relative_label = |path: &Path| match &snapshot {
+        Some(snapshot) => format::label(&snapshot.working_path(path, &args.src_dir), &args.src_dir, PathStyle::Relative),
+        None => format::label(path, &args.src_dir, PathStyle::Relative),
+    };
+
+    // The bundle must not end up in itself, nor an earlier bundle or its parts.
+    let outputs = OutputFiles::new(&args.out_dir, &args.bundle_name, args.extension());
+    files.retain(|path| {
+        let own = outputs.contains(path);
+        if own && args.verbose {
+            status!(args, "Excluding {}: it is written by fbundle as part of this bundle", path.display());
+        }
+        !own
+    });
+
+    let priority = order::priority_matchers(root, &args.priority)?;
+    order::sort_files(&mut files, args.sort, &priority);
 
     if args.verbose {
-        println!("Total files to process: {}", files.len());
+        status!(args, "Total files to process: {}", files.len());
     }
 
-    files.par_iter().try_for_each(|entry| -> io::Result<()> {
-        let path = entry.path();
-        
+    let load_options = LoadOptions {
+        binary: args.binary,
+        input_encoding: args.input_encoding.as_deref().map(content::encoding_for_label).transpose()?,
+        lossy: args.lossy,
+    };
+
+    let header = Template::parse(args.header_template.as_deref().unwrap_or(template::DEFAULT_HEADER))?;
+    let footer = args.footer_template.as_deref().map(Template::parse).transpose()?;
+    // Files of a snapshot are new on every run, so there is nothing to reuse.
+    let cache = match cache::dir().filter(|_| !args.no_cache && snapshot.is_none()) {
+        Some(dir) => match Cache::open(dir.clone(), load_options) {
+            Ok(cache) => Some(cache),
+            Err(err) => {
+                eprintln!("Warning: Not using the cache in {}: {}", dir.display(), err);
+                None
+            }
+        },
+        None => None,
+    };
+    let cache_hits = AtomicUsize::new(0);
+
+    let needs_sha256 =
+        header.uses_with(Field::Sha256, file_sep) || footer.as_ref().is_some_and(|footer| footer.uses_with(Field::Sha256, file_sep));
+
+    // Inspect files in parallel; `collect` keeps the results in `files` order.
+    let sources = files.par_iter().map(|path| -> io::Result<Option<Source>> {
         if args.verbose {
-            println!("Processing file: {}", path.display());
+            status!(args, "Processing file: {}", path.display());
         }
 
-        let mut file = File::open(path)?;
-        let mut contents = Vec::new();
-        file.read_to_end(&mut contents)?;
-
-        let file_content = match String::from_utf8(contents.clone()) {
-            Ok(s) => s,
-            Err(_) => {
-                eprintln!("Warning: File {} is not valid UTF-8. Skipping content.", path.display());
-                String::new()
+        let label = label(path, args.path_style);
+        if let Some(source) = cache.as_ref().and_then(|cache| cache.get(path, &label)) {
+            cache_hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(source);
+        }
+        let stamp = cache.as_ref().and_then(|_| Stamp::of(path).ok());
+        let mut source = content::inspect(path, label, load_options)?;
+        if let (Some(cache), Some(stamp)) = (&cache, stamp) {
+            if let Err(err) = cache.put(path, stamp, source.as_ref()) {
+                if args.verbose {
+                    status!(args, "Could not cache {}: {}", path.display(), err);
+                }
             }
+        }
+        if let Some(source) = source.as_mut().filter(|_| needs_sha256) {
+            source.sha256 = Some(content::sha256_file(path)?);
+        }
+        if args.verbose {
+            match &source {
+                None => status!(args, "Skipping binary file: {}", path.display()),
+                Some(source) if source.encoding != format::TEXT => {
+                    status!(args, "File {} written as {}", path.display(), source.encoding)
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(source)
+    }).collect::<io::Result<Vec<_>>>()?;
+
+    if args.verbose && cache.is_some() {
+        status!(args, "Reused {} of {} files from the cache", cache_hits.into_inner(), files.len());
+    }
+
+    let mut sources: Vec<Source> = sources.into_iter().flatten().collect();
+    let markers = Markers { file_sep, header: &header, footer: footer.as_ref() };
+
+    if let Some(spec) = &args.diff_against {
+        let wanted = sources.iter().map(|source| PathBuf::from(relative_label(&source.path))).collect();
+        let unescape = args.sep_collision == SepCollision::Escape;
+        let baseline = Baseline::open(spec, &args.src_dir, &wanted, &markers, unescape)?;
+        sources = sources
+            .into_par_iter()
+            .map(|source| {
+                let path = source.path.clone();
+                let compared = baseline.compare(source, &relative_label(&path), args.diff_context, load_options)?;
+                if compared.is_none() && args.verbose {
+                    status!(args, "Leaving out {}: unchanged since {}", path.display(), spec);
+                }
+                Ok(compared)
+            })
+            .collect::<io::Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .collect();
+    }
+
+    // Lines of the files that would be read back as a header or footer make the bundle ambiguous.
+    // Markdown bundles are read back a code block at a time, so nothing inside one is a header.
+    let file_sep = match args.format {
+        Format::Plain => check_collisions(args, &mut sources, &markers)?,
+        Format::Markdown | Format::Json | Format::Jsonl | Format::Xml => file_sep.clone(),
+    };
+    let renderer = Renderer { format: args.format, file_sep: &file_sep, header: &header, footer: footer.as_ref() };
+    let limits = Limits { max_bytes: args.max_bytes, max_tokens: args.split_tokens(), tokenizer: args.tokenizer };
+    let embedded_manifest = args.manifest == Some(ManifestMode::Embedded);
+    number_blocks(&mut sources, embedded_manifest);
+
+    let mut counts = Vec::new();
+    if args.count_tokens || budget.is_some() || args.tree_info.contains(&TreeInfo::Tokens) {
+        counts = sources
+            .par_iter()
+            .enumerate()
+            .map(|(i, source)| {
+                // A diff depends on the baseline as well as the file, so its count is not cached.
+                let Some(cache) = cache.as_ref().filter(|_| source.encoding != format::DIFF) else {
+                    return output::block_tokens(&renderer, args.tokenizer, source, i == 0);
+                };
+                let key = cache::token_key(&renderer, args.tokenizer, source, i == 0);
+                if let Some(tokens) = cache.tokens(source, &key) {
+                    return Ok(tokens);
+                }
+                let tokens = output::block_tokens(&renderer, args.tokenizer, source, i == 0)?;
+                // A count that cannot be cached is simply counted again next time.
+                let _ = cache.put_tokens(source, key, tokens);
+                Ok(tokens)
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+    }
+
+    let mut present = match &snapshot {
+        _ if !(args.tree && args.tree_excluded) => Vec::new(),
+        Some(snapshot) => snapshot.all.clone(),
+        None => select::walk_all(WalkOptions::of(args)),
+    };
+    present.retain(|path| !outputs.contains(path));
+    let tree_title = match &snapshot {
+        Some(snapshot) => format!("{}:{}", snapshot.rev, args.src_dir.display()),
+        None => args.src_dir.display().to_string(),
+    };
+    let render_tree = |sources: &[Source], counts: &[usize]| {
+        args.tree
+            .then(|| tree::render(root, &tree_title, sources, counts, &args.tree_info, &present))
+            .and_then(|tree| renderer.tree(&tree))
+    };
+    let mut tree = render_tree(&sources, &counts);
+
+    if args.count_tokens || budget.is_some() {
+        let overhead = args.tokenizer.count(&renderer.begin()) + args.tokenizer.count(&renderer.end());
+        let total = |tree: &Option<String>, counts: &[usize]| {
+            overhead + tree.as_deref().map_or(0, |tree| args.tokenizer.count(tree)) + counts.iter().sum::<usize>()
         };
+        let mut total_tokens = total(&tree, &counts);
+
+        if let (Some(max), Overflow::Drop) = (budget, args.overflow) {
+            let drop_last = |sources: &mut Vec<Source>, counts: &mut Vec<usize>| match (sources.pop(), counts.pop()) {
+                (Some(source), Some(tokens)) => {
+                    eprintln!("Warning: Dropping {} ({} tokens) to stay within --max-tokens {}", source.label, tokens, max);
+                    Some(tokens)
+                }
+                _ => None,
+            };
+            while total_tokens > max {
+                let Some(tokens) = drop_last(&mut sources, &mut counts) else { break };
+                total_tokens -= tokens;
+            }
+            // Leaving files out changes the tree, so check again with the final one.
+            loop {
+                tree = render_tree(&sources, &counts);
+                total_tokens = total(&tree, &counts);
+                if total_tokens <= max || drop_last(&mut sources, &mut counts).is_none() {
+                    break;
+                }
+            }
+            number_blocks(&mut sources, embedded_manifest);
+        }
+
+        if args.count_tokens {
+            status!(args, "Tokens ({}):", args.tokenizer.name());
+            if let Some(tree) = &tree {
+                status!(args, "{:>10}  (tree)", args.tokenizer.count(tree));
+            }
+            for (source, tokens) in sources.iter().zip(&counts) {
+                status!(args, "{:>10}  {}", tokens, source.label);
+            }
+            status!(args, "{:>10}  total", total_tokens);
+        }
+
+        if let Some(max) = budget.filter(|&max| total_tokens > max) {
+            return Err(io::Error::other(format!(
+                "the bundle has {} tokens, more than --max-tokens {}; nothing was written",
+                total_tokens, max
+            )));
+        }
+    }
+
+    let manifest = match args.manifest {
+        Some(_) => {
+            let bundled: HashSet<&Path> = sources.iter().map(|source| source.path.as_path()).collect();
+            let selected: Vec<_> = files
+                .iter()
+                .map(|path| {
+                    // The manifest describes the source directory, so that `verify` can compare it with the revision.
+                    (path.clone(), relative_label(path), bundled.contains(path.as_path()))
+                })
+                .collect();
+            let selection = Selection {
+                src_globs: args.src_globs.clone(),
+                respect_gitignore: args.respect_gitignore,
+                respect_ignore_files: args.respect_ignore_files,
+                custom_ignore_file: args.custom_ignore_file.clone(),
+                files_from: args.files_from.is_some() || args.uses_git(),
+                git_rev: args.git_rev.clone(),
+            };
+            Some(Manifest::build(selection, &selected)?)
+        }
+        None => None,
+    };
+
+    // Stream the files into the bundle one at a time, in order.
+    let mut output = if args.to_stdout() {
+        Output::stdout(&renderer)?
+    } else {
+        Output::create(&renderer, limits, &args.out_dir, &args.bundle_name, args.extension())?
+    };
+    let written = tree
+        .as_deref()
+        .map_or(Ok(()), |tree| output.write_tree(tree))
+        .and_then(|()| sources.iter().try_for_each(|source| output.write(source)))
+        .and_then(|()| match (&manifest, args.manifest) {
+            (Some(manifest), Some(ManifestMode::Embedded)) => {
+                let mut block = Source::text(manifest::EMBEDDED_PATH.to_string(), manifest.to_json());
+                block.index = sources.len() + 1;
+                block.count = sources.len() + 1;
+                output.write(&block)
+            }
+            (Some(manifest), Some(ManifestMode::Sidecar)) => {
+                output.write_file(manifest::sidecar_path(&args.out_dir, &args.bundle_name), &manifest.to_json())
+            }
+            _ => Ok(()),
+        });
+    let summary = match written.and_then(|()| output.finish()) {
+        // The reader of a pipe went away (e.g. `| head`); that is not an error for us.
+        Err(err) if args.to_stdout() && err.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+        result => result?,
+    };
+
+    if args.to_stdout() {
+        status!(args, "Bundle written to stdout");
+    } else if limits.is_set() {
+        status!(args, "Bundle created in {} parts, index at: {}", summary.parts, summary.path.display());
+    } else {
+        status!(args, "Bundle created at: {}", summary.path.display());
+    }
 
-        let output = format!("{} {}\n{}\n", args.file_sep, path.display(), file_content);
-        
-        let mut writer = writer.lock().unwrap();
-        writer.write_all(output.as_bytes())?;
-        Ok(())
-    })?;
-    
-    writer.lock().unwrap().flush()?;
-    
-    println!("Bundle created at: {}", out_path.display());
+    if args.manifest == Some(ManifestMode::Sidecar) {
+        status!(args, "Manifest written to: {}", manifest::sidecar_path(&args.out_dir, &args.bundle_name).display());
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Numbers the blocks of the bundle for `{index}` and `{count}`; an embedded
+/// manifest comes last.
+fn number_blocks(sources: &mut [Source], embedded_manifest: bool) {
+    let count = sources.len() + usize::from(embedded_manifest);
+    for (i, source) in sources.iter_mut().enumerate() {
+        source.index = i + 1;
+        source.count = count;
+    }
+}
+
+/// Finds lines of `sources` that would be read back as a header or footer,
+/// picks a separator they do not collide with for `--sep-auto`, then deals
+/// with the remaining ones as `--sep-collision` says. Returns the separator.
+fn check_collisions(args: &FileBundle, sources: &mut [Source], markers: &Markers) -> io::Result<Template> {
+    let lines = sources
+        .par_iter()
+        .map(|source| {
+            if format::is_binary(source.encoding) {
+                Ok(collision::Suspicious::default())
+            } else {
+                collision::suspicious_lines(source, markers, args.sep_auto)
+            }
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let file_sep = if args.sep_auto {
+        let taken = lines.iter().flat_map(|suspicious| &suspicious.taken).copied().collect();
+        let file_sep = collision::unique_sep(markers, &taken);
+        status!(args, "File separator: {}", file_sep.source());
+        file_sep
+    } else {
+        markers.file_sep.clone()
+    };
+    let markers = Markers { file_sep: &file_sep, ..*markers };
+
+    let mut colliding = 0;
+    for (source, lines) in sources.iter_mut().zip(lines.iter().map(|suspicious| &suspicious.windows)) {
+        if args.sep_collision == SepCollision::Escape {
+            if lines.iter().any(|(_, line)| markers.needs_escape(line)) {
+                let escaped = markers.escape(&source.body_text()?).into_owned();
+                *source = source.clone().with_text(escaped);
+            }
+            continue;
+        }
+        let mut collisions = lines.iter().filter(|(_, line)| markers.collides(line));
+        if let Some((number, line)) = collisions.next() {
+            let more = collisions.count();
+            let more = if more > 0 { format!(" and {} more", more) } else { String::new() };
+            eprintln!(
+                "{}: Line {} of {}{} would be read back as a file header or footer: {:?}",
+                if args.sep_collision == SepCollision::Fail { "Error" } else { "Warning" },
+                number,
+                source.label,
+                more,
+                line,
+            );
+            colliding += 1;
+        }
+    }
+    if colliding > 0 && args.sep_collision == SepCollision::Fail {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} files have lines that look like file headers; nothing was written (use --sep-auto or --sep-collision escape)",
+                colliding
+            ),
+        ));
+    }
+    if colliding > 0 {
+        eprintln!("Warning: Use --sep-auto or --sep-collision escape to keep the bundle readable by 'unbundle'.");
+    }
+    Ok(file_sep)
+}
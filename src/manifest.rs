@@ -0,0 +1,128 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use clap::ValueEnum;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::content;
+
+/// Where the manifest goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ManifestMode {
+    /// A separate <bundle_name>.manifest.json next to the bundle
+    Sidecar,
+    /// A last block in the bundle with the path fbundle.manifest.json
+    Embedded,
+}
+
+/// The path of the block holding an embedded manifest.
+pub const EMBEDDED_PATH: &str = "fbundle.manifest.json";
+
+/// Current version of the manifest layout. The key also marks the start of an
+/// embedded manifest.
+const VERSION: u32 = 1;
+const MARKER: &str = "\"fbundle_manifest\"";
+
+/// What a bundle was made from: every selected file with its hash, so the
+/// source tree can be checked against it later by `fbundle verify`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub fbundle_manifest: u32,
+    pub selection: Selection,
+    pub files: Vec<FileEntry>,
+}
+
+/// How the files were selected, so that `verify` can spot files added since.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Selection {
+    pub src_globs: Vec<String>,
+    pub respect_gitignore: bool,
+    pub respect_ignore_files: bool,
+    pub custom_ignore_file: Vec<String>,
+    /// The files came from `--files-from` or git rather than from walking the source directory.
+    pub files_from: bool,
+    /// The revision of `--git-rev`, which labels the paths in the bundle as `<rev>:<path>`.
+    #[serde(default)]
+    pub git_rev: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileEntry {
+    /// Relative to the source directory.
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+    /// Unix permission bits in octal, e.g. `0644`.
+    pub mode: Option<String>,
+    /// Seconds since the Unix epoch.
+    pub mtime: Option<u64>,
+    /// False for selected files that are not in the bundle, e.g. skipped binary files.
+    pub bundled: bool,
+}
+
+impl FileEntry {
+    /// Reads the size, hash, mode and mtime of `path`.
+    pub fn of(path: &Path, label: String, bundled: bool) -> io::Result<Self> {
+        let meta = fs::metadata(path)?;
+        let mtime = meta.modified().ok().and_then(|time| time.duration_since(UNIX_EPOCH).ok()).map(|time| time.as_secs());
+        Ok(FileEntry { path: label, size: meta.len(), sha256: content::sha256_file(path)?, mode: mode(&meta), mtime, bundled })
+    }
+}
+
+#[cfg(unix)]
+fn mode(meta: &fs::Metadata) -> Option<String> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(format!("{:04o}", meta.permissions().mode() & 0o7777))
+}
+
+#[cfg(not(unix))]
+fn mode(_meta: &fs::Metadata) -> Option<String> {
+    None
+}
+
+impl Manifest {
+    /// Hashes `files` in parallel. Each file is `(path on disk, path relative
+    /// to the source directory, whether it is in the bundle)`.
+    pub fn build(selection: Selection, files: &[(PathBuf, String, bool)]) -> io::Result<Self> {
+        let files = files
+            .par_iter()
+            .map(|(path, label, bundled)| FileEntry::of(path, label.clone(), *bundled))
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(Manifest { fbundle_manifest: VERSION, selection, files })
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("manifests always serialize") + "\n"
+    }
+
+    /// Finds an embedded manifest in the text of a bundle in any format.
+    pub fn find_embedded(bundle: &str) -> Option<Self> {
+        // In JSON and JSON Lines bundles the manifest is an escaped string.
+        let from_value = |value: &serde_json::Value| {
+            (value.get("path")?.as_str()? == EMBEDDED_PATH)
+                .then(|| serde_json::from_str(value.get("contents")?.as_str()?).ok())
+                .flatten()
+        };
+        if let Ok(serde_json::Value::Array(blocks)) = serde_json::from_str(bundle) {
+            return blocks.iter().find_map(from_value);
+        }
+        if let Some(manifest) = bundle.lines().filter_map(|line| serde_json::from_str(line).ok()).find_map(|value| from_value(&value)) {
+            return Some(manifest);
+        }
+
+        // Elsewhere it is the raw JSON text; the last one wins.
+        bundle.rmatch_indices(MARKER).find_map(|(marker, _)| {
+            let start = bundle[..marker].rfind('{')?;
+            serde_json::Deserializer::from_str(&bundle[start..]).into_iter::<Manifest>().next()?.ok()
+        })
+    }
+}
+
+/// The sidecar manifest of the bundle `name` in `dir`.
+pub fn sidecar_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}.manifest.json", name))
+}
@@ -0,0 +1,62 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use clap::ValueEnum;
+use ignore::overrides::{Override, OverrideBuilder};
+use serde::Deserialize;
+
+/// The order in which files are written to the bundle.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    /// Sort by path
+    #[default]
+    Path,
+    /// Keep the order the directory walker or --files-from produced
+    Walk,
+    /// Smallest files first
+    Size,
+    /// Least recently modified files first
+    Mtime,
+}
+
+/// Builds one matcher per `--priority` glob, using the same glob semantics as `--src-globs`.
+pub fn priority_matchers(src_dir: &Path, globs: &[String]) -> io::Result<Vec<Override>> {
+    globs
+        .iter()
+        .map(|glob| {
+            let mut builder = OverrideBuilder::new(src_dir);
+            builder.add(glob).map_err(io::Error::other)?;
+            builder.build().map_err(io::Error::other)
+        })
+        .collect()
+}
+
+/// Sorts `files` in place. Files matching an earlier priority glob come first;
+/// within the same priority the sort order applies, with the path as a tie-breaker
+/// so the result never depends on the walk.
+pub fn sort_files(files: &mut [PathBuf], order: SortOrder, priority: &[Override]) {
+    let rank = |path: &Path| {
+        priority
+            .iter()
+            .position(|matcher| matcher.matched(path, false).is_whitelist())
+            .unwrap_or(priority.len())
+    };
+
+    match order {
+        SortOrder::Walk => files.sort_by_cached_key(|path| rank(path)),
+        SortOrder::Path => files.sort_by_cached_key(|path| (rank(path), path.clone())),
+        SortOrder::Size => files.sort_by_cached_key(|path| {
+            let size = fs::metadata(path).map_or(0, |meta| meta.len());
+            (rank(path), size, path.clone())
+        }),
+        SortOrder::Mtime => files.sort_by_cached_key(|path| {
+            let modified = fs::metadata(path)
+                .and_then(|meta| meta.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            (rank(path), modified, path.clone())
+        }),
+    }
+}
@@ -0,0 +1,399 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::atomic::Pending;
+use crate::content::Source;
+use crate::format::Renderer;
+use crate::tokens::Tokenizer;
+
+/// Suffix added to the header path of every piece after the first when a
+/// single file has to be split across parts.
+pub const CONTINUED: &str = " (continued)";
+
+/// What to do when the bundle has more than `--max-tokens` tokens.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Overflow {
+    /// Split the bundle into parts of at most --max-tokens tokens each
+    #[default]
+    Split,
+    /// Fail without writing the bundle
+    Fail,
+    /// Leave out files from the end of the bundle, the lowest-priority ones, until it fits
+    Drop,
+}
+
+/// Upper bounds for a single bundle part.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub max_bytes: Option<usize>,
+    pub max_tokens: Option<usize>,
+    pub tokenizer: Tokenizer,
+}
+
+impl Limits {
+    pub fn is_set(&self) -> bool {
+        self.max_bytes.is_some() || self.max_tokens.is_some()
+    }
+
+    fn cost(&self, text: &str) -> Cost {
+        let tokens = if self.max_tokens.is_some() { self.tokenizer.count(text) } else { 0 };
+        Cost { bytes: text.len(), tokens }
+    }
+
+    fn fits(&self, cost: Cost) -> bool {
+        self.max_bytes.is_none_or(|max| cost.bytes <= max) && self.max_tokens.is_none_or(|max| cost.tokens <= max)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Cost {
+    bytes: usize,
+    tokens: usize,
+}
+
+impl Cost {
+    fn plus(self, other: Cost) -> Self {
+        Cost { bytes: self.bytes + other.bytes, tokens: self.tokens + other.tokens }
+    }
+}
+
+/// Recognises the files written for a bundle, so that they are never read
+/// back as its sources: the bundle itself, the parts and index of a split
+/// bundle, the sidecar manifest, and temporary files of any of these.
+#[derive(Debug)]
+pub struct OutputFiles {
+    /// The canonical output directory, if it exists.
+    dir: Option<PathBuf>,
+    name: String,
+    extension: String,
+}
+
+impl OutputFiles {
+    pub fn new(dir: &Path, name: &str, extension: &str) -> Self {
+        OutputFiles { dir: dir.canonicalize().ok(), name: name.to_string(), extension: extension.to_string() }
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+            return false;
+        };
+        let file_name = match file_name.strip_suffix(".tmp").and_then(|rest| rest.rsplit_once('.')) {
+            Some((rest, pid)) if is_number(pid) => rest,
+            _ => file_name,
+        };
+        let Some(rest) = file_name.strip_prefix(self.name.as_str()) else {
+            return false;
+        };
+        let is_part = rest
+            .strip_suffix(self.extension.as_str())
+            .and_then(|rest| rest.strip_prefix('.'))
+            .is_some_and(|part| part.len() >= 3 && is_number(part));
+        let is_output = rest == self.extension || rest == ".index.txt" || rest == ".manifest.json" || is_part;
+        if !is_output || self.dir.is_none() {
+            return false;
+        }
+        // Only compare directories for names that match, as this touches the disk.
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        dir.canonicalize().ok() == self.dir
+    }
+}
+
+fn is_number(text: &str) -> bool {
+    !text.is_empty() && text.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// What `Output::finish` wrote.
+#[derive(Debug)]
+pub struct Summary {
+    /// The bundle, or the index of a split bundle.
+    pub path: PathBuf,
+    pub parts: usize,
+}
+
+/// Streams blocks into the bundle file, or, when limits are set, into
+/// `file_bundle.001.txt`, `file_bundle.002.txt`, ... plus an index listing
+/// which file went into which part. Each part is a complete document.
+///
+/// Blocks are never split unless a single block is larger than a whole part;
+/// such a block starts a fresh part and is cut at line boundaries (or inside a
+/// line, as a last resort) with `CONTINUED` appended to every later header.
+///
+/// Every file is written under a temporary name and only renamed into place by
+/// `finish`, so that readers never see a half-written bundle and a failed run
+/// leaves the previous bundle intact.
+pub struct Output<'a> {
+    renderer: &'a Renderer<'a>,
+    limits: Limits,
+    dir: PathBuf,
+    name: String,
+    extension: String,
+    index: Option<BufWriter<File>>,
+    writer: Box<dyn Write>,
+    /// The files of the bundle, until it is complete.
+    pending: Pending,
+    parts: usize,
+    /// Header paths of the blocks in the current part.
+    files: Vec<String>,
+    /// Cost of the current part, including its `end()`.
+    cost: Cost,
+    /// Whether the contents of the current block are empty or end in a newline.
+    at_line_start: bool,
+}
+
+impl<'a> Output<'a> {
+    pub fn create(renderer: &'a Renderer<'a>, limits: Limits, dir: &Path, name: &str, extension: &str) -> io::Result<Self> {
+        let mut output = Output {
+            renderer,
+            limits,
+            dir: dir.to_path_buf(),
+            name: name.to_string(),
+            extension: extension.to_string(),
+            index: None,
+            writer: Box::new(io::sink()),
+            pending: Pending::default(),
+            parts: 1,
+            files: Vec::new(),
+            cost: Cost::default(),
+            at_line_start: true,
+        };
+        let path = if limits.is_set() {
+            output.index = Some(BufWriter::new(output.create_file(dir.join(format!("{}.index.txt", name)))?));
+            output.part_path(1)
+        } else {
+            dir.join(format!("{}{}", name, extension))
+        };
+        output.writer = Box::new(BufWriter::new(output.create_file(path)?));
+        output.begin_part()?;
+        Ok(output)
+    }
+
+    /// Streams the bundle to stdout. Bundles written to stdout are never split.
+    pub fn stdout(renderer: &'a Renderer<'a>) -> io::Result<Self> {
+        let mut output = Output {
+            renderer,
+            limits: Limits { max_bytes: None, max_tokens: None, tokenizer: Tokenizer::default() },
+            dir: PathBuf::new(),
+            name: String::new(),
+            extension: String::new(),
+            index: None,
+            writer: Box::new(BufWriter::new(io::stdout().lock())),
+            pending: Pending::default(),
+            parts: 1,
+            files: Vec::new(),
+            cost: Cost::default(),
+            at_line_start: true,
+        };
+        output.begin_part()?;
+        Ok(output)
+    }
+
+    pub fn write(&mut self, source: &Source) -> io::Result<()> {
+        let renderer = self.renderer;
+        let limits = self.limits;
+        if limits.is_set() {
+            let body = measure(renderer, limits, source)?;
+            let block = |first| {
+                limits
+                    .cost(&renderer.block_start(source, &source.label, first))
+                    .plus(body)
+                    .plus(limits.cost(&renderer.block_end(source, &source.label, false)))
+            };
+            if !limits.fits(self.cost.plus(block(self.files.is_empty()))) {
+                let fresh = limits.cost(&renderer.begin()).plus(limits.cost(&renderer.end()));
+                if !self.files.is_empty() {
+                    self.next_part()?;
+                }
+                if !limits.fits(fresh.plus(block(true))) {
+                    return self.write_split(source);
+                }
+            }
+        }
+
+        self.start_block(source, &source.label)?;
+        source.read_body(|text| self.write_content(text))?;
+        self.end_block(source, &source.label)
+    }
+
+    /// Writes text rendered by `Renderer::tree` into the current part.
+    pub fn write_tree(&mut self, tree: &str) -> io::Result<()> {
+        self.emit(tree)
+    }
+
+    /// Writes a file that belongs with the bundle, such as a sidecar manifest.
+    /// It is moved into place together with the bundle.
+    pub fn write_file(&mut self, path: PathBuf, contents: &str) -> io::Result<()> {
+        self.create_file(path)?.write_all(contents.as_bytes())
+    }
+
+    /// Writes the final part and the index, and moves all files into place.
+    pub fn finish(mut self) -> io::Result<Summary> {
+        self.end_part()?;
+        self.writer = Box::new(io::sink());
+        let path = match self.index.take() {
+            Some(mut index) => {
+                index.flush()?;
+                self.dir.join(format!("{}.index.txt", self.name))
+            }
+            None => self.dir.join(format!("{}{}", self.name, self.extension)),
+        };
+        self.pending.commit()?;
+        // Parts left over from an earlier, longer split bundle are no longer in the index.
+        if self.limits.is_set() {
+            for stale in self.parts + 1.. {
+                match fs::remove_file(self.part_path(stale)) {
+                    Ok(()) => {}
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => break,
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+        Ok(Summary { path, parts: self.parts })
+    }
+
+    /// Creates a temporary file that `finish` renames to `path`.
+    fn create_file(&mut self, path: PathBuf) -> io::Result<File> {
+        self.pending.create(path)
+    }
+
+    fn part_name(&self, part: usize) -> String {
+        format!("{}.{:03}{}", self.name, part, self.extension)
+    }
+
+    fn part_path(&self, part: usize) -> PathBuf {
+        self.dir.join(self.part_name(part))
+    }
+
+    /// Writes a block that does not fit into a part of its own, starting a new
+    /// part whenever the next line would not fit.
+    fn write_split(&mut self, source: &Source) -> io::Result<()> {
+        let renderer = self.renderer;
+        let limits = self.limits;
+        let continued = format!("{}{}", source.label, CONTINUED);
+        let end = limits.cost(&renderer.block_end(source, &continued, false));
+
+        let mut label = source.label.clone();
+        self.start_block(source, &label)?;
+        let mut has_content = false;
+        source.read_body(|text| {
+            for line in text.split_inclusive('\n') {
+                let mut line = line;
+                while !line.is_empty() {
+                    if limits.fits(self.cost.plus(limits.cost(&renderer.content(line))).plus(end)) {
+                        self.write_content(line)?;
+                        has_content = true;
+                        break;
+                    }
+                    if has_content {
+                        self.end_block(source, &label)?;
+                        label = continued.clone();
+                        self.next_part()?;
+                        self.start_block(source, &continued)?;
+                        has_content = false;
+                        continue;
+                    }
+                    // A single line is larger than a part: take the longest prefix that fits.
+                    let cut = longest_fitting_prefix(line, |prefix| {
+                        limits.fits(self.cost.plus(limits.cost(&renderer.content(prefix))).plus(end))
+                    });
+                    self.write_content(&line[..cut])?;
+                    has_content = true;
+                    line = &line[cut..];
+                }
+            }
+            Ok(())
+        })?;
+        self.end_block(source, &label)
+    }
+
+    fn start_block(&mut self, source: &Source, label: &str) -> io::Result<()> {
+        let start = self.renderer.block_start(source, label, self.files.is_empty());
+        self.emit(&start)?;
+        self.files.push(label.to_string());
+        self.at_line_start = true;
+        Ok(())
+    }
+
+    fn write_content(&mut self, text: &str) -> io::Result<()> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        let content = self.renderer.content(text);
+        self.emit(&content)?;
+        self.at_line_start = text.ends_with('\n');
+        Ok(())
+    }
+
+    fn end_block(&mut self, source: &Source, label: &str) -> io::Result<()> {
+        let end = self.renderer.block_end(source, label, self.at_line_start);
+        self.emit(&end)
+    }
+
+    fn emit(&mut self, text: &str) -> io::Result<()> {
+        if self.limits.is_set() {
+            self.cost = self.cost.plus(self.limits.cost(text));
+        }
+        self.writer.write_all(text.as_bytes())
+    }
+
+    fn begin_part(&mut self) -> io::Result<()> {
+        self.cost = self.limits.cost(&self.renderer.end());
+        let begin = self.renderer.begin();
+        self.emit(&begin)
+    }
+
+    fn end_part(&mut self) -> io::Result<()> {
+        self.writer.write_all(self.renderer.end().as_bytes())?;
+        self.writer.flush()?;
+        let part_name = self.part_name(self.parts);
+        if let Some(index) = &mut self.index {
+            for path in self.files.drain(..) {
+                writeln!(index, "{}\t{}", part_name, path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn next_part(&mut self) -> io::Result<()> {
+        self.end_part()?;
+        self.parts += 1;
+        let file = self.create_file(self.part_path(self.parts))?;
+        self.writer = Box::new(BufWriter::new(file));
+        self.files.clear();
+        self.begin_part()
+    }
+}
+
+/// The cost of the contents of `source` once rendered.
+fn measure(renderer: &Renderer, limits: Limits, source: &Source) -> io::Result<Cost> {
+    let mut cost = Cost::default();
+    source.read_body(|text| {
+        cost = cost.plus(limits.cost(&renderer.content(text)));
+        Ok(())
+    })?;
+    Ok(cost)
+}
+
+/// The number of tokens `source` adds to a bundle, header and footer included.
+pub fn block_tokens(renderer: &Renderer, tokenizer: Tokenizer, source: &Source, first: bool) -> io::Result<usize> {
+    let limits = Limits { max_bytes: None, max_tokens: Some(usize::MAX), tokenizer };
+    let start = limits.cost(&renderer.block_start(source, &source.label, first));
+    let at_line_start = source.stats.bytes == 0 || source.stats.ends_with_newline;
+    let end = limits.cost(&renderer.block_end(source, &source.label, at_line_start));
+    Ok(start.plus(measure(renderer, limits, source)?).plus(end).tokens)
+}
+
+/// The length of the longest prefix of `line` for which `fits` holds, but at
+/// least one character so that splitting always makes progress.
+fn longest_fitting_prefix(line: &str, fits: impl Fn(&str) -> bool) -> usize {
+    let boundaries: Vec<usize> = line.char_indices().map(|(i, _)| i).skip(1).chain([line.len()]).collect();
+    let fitting = boundaries.partition_point(|&end| fits(&line[..end]));
+    boundaries[fitting.saturating_sub(1)]
+}
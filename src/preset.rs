@@ -0,0 +1,147 @@
+use std::io;
+use std::path::Path;
+
+use clap::{Args, ValueEnum};
+
+use crate::format::Format;
+use crate::template::{self, Field, Template};
+
+/// A named layout: a format with a separator and templates that go together.
+#[derive(Debug)]
+pub struct Preset {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub format: Format,
+    /// Written as on the command line, with escapes.
+    pub file_sep: &'static str,
+    pub header_template: &'static str,
+    pub footer_template: Option<&'static str>,
+}
+
+pub const PRESETS: &[Preset] = &[
+    Preset {
+        name: "claude-xml",
+        description: "Each file in numbered <document> tags with its <source>, as Anthropic suggests for long documents",
+        format: Format::Plain,
+        file_sep: "<document index=\"{index}\">",
+        header_template: "{sep}<source>{path}</source><document_content>",
+        footer_template: Some("</document_content></document>"),
+    },
+    Preset {
+        name: "markdown-fence",
+        description: "A '## path' heading per file followed by a fenced code block",
+        format: Format::Markdown,
+        file_sep: "##",
+        header_template: template::DEFAULT_HEADER,
+        footer_template: None,
+    },
+    Preset {
+        name: "gpt-plain",
+        description: "Plain text with the path between rules of equals signs",
+        format: Format::Plain,
+        file_sep: "=====",
+        header_template: "{sep} {path} {sep}",
+        footer_template: None,
+    },
+    Preset {
+        name: "rfc822-like",
+        description: "A boundary line and Path, Size and Lines headers, then an empty line, like a mail message",
+        format: Format::Plain,
+        file_sep: "--fbundle-boundary\\nPath: {path}\\nSize: {size}\\nLines: {lines}\\n",
+        header_template: "{sep}",
+        footer_template: None,
+    },
+];
+
+/// Looks up a preset by name.
+pub fn find(name: &str) -> io::Result<&'static Preset> {
+    PRESETS.iter().find(|preset| preset.name == name).ok_or_else(|| {
+        let known: Vec<&str> = PRESETS.iter().map(|preset| preset.name).collect();
+        io::Error::new(io::ErrorKind::InvalidInput, format!("no preset '{}' (available: {})", name, known.join(", ")))
+    })
+}
+
+/// The options of `unbundle` and `apply` that say how a bundle was written.
+#[derive(Debug, Args)]
+pub struct LayoutArgs {
+    /// The separator the bundle was created with [default: '##' for .md bundles, '---' otherwise]
+    #[arg(short = 'f', long, allow_hyphen_values = true)]
+    pub file_sep: Option<String>,
+
+    /// The --header-template the bundle was created with [default: '{sep} {path}']
+    #[arg(long, allow_hyphen_values = true)]
+    pub header_template: Option<String>,
+
+    /// The --footer-template the bundle was created with, if any
+    #[arg(long, allow_hyphen_values = true)]
+    pub footer_template: Option<String>,
+
+    /// The --preset the bundle was created with, for the options above not given
+    #[arg(long)]
+    pub preset: Option<String>,
+
+    /// The --format the bundle was created with: plain or markdown [default: markdown for .md bundles]
+    #[arg(long, value_enum)]
+    pub format: Option<Format>,
+
+    /// Remove the backslashes written by --sep-collision escape
+    #[arg(long, default_value_t = false)]
+    pub unescape: bool,
+
+    /// The --git-rev the bundle was created with, taken off the front of the paths [default: the one its manifest records]
+    #[arg(long, value_name = "REV")]
+    pub git_rev: Option<String>,
+}
+
+/// How a plain or Markdown bundle was written, as `unbundle` and `apply` need
+/// to know it to read the bundle back.
+#[derive(Debug)]
+pub struct Layout {
+    pub format: Format,
+    pub file_sep: Template,
+    pub header: Template,
+    pub footer: Option<Template>,
+}
+
+/// The layout a bundle was written with: the options given, then those of the
+/// preset, then the defaults for the extension of `bundle`, where `.md` stands
+/// for `--format markdown`.
+pub fn read_back(args: &LayoutArgs, bundle: &Path) -> io::Result<Layout> {
+    let preset = args.preset.as_deref().map(find).transpose()?;
+    let extension = bundle.extension().map(|ext| format!(".{}", ext.to_string_lossy())).unwrap_or_default();
+    let by_extension = if extension == Format::Markdown.default_extension() { Format::Markdown } else { Format::Plain };
+    let format = args.format.or(preset.map(|preset| preset.format)).unwrap_or(by_extension);
+    if !matches!(format, Format::Plain | Format::Markdown) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "only plain and Markdown bundles can be read back"));
+    }
+    let file_sep = args
+        .file_sep
+        .as_deref()
+        .or(preset.map(|preset| preset.file_sep))
+        .unwrap_or_else(|| format.default_file_sep(&extension));
+    let file_sep = Template::separator(file_sep)?;
+    let header = args.header_template.as_deref().or(preset.map(|preset| preset.header_template));
+    let header = Template::parse(header.unwrap_or(template::DEFAULT_HEADER))?;
+    if !header.uses_with(Field::Path, &file_sep) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "the header template must contain {path}"));
+    }
+    let footer = args.footer_template.as_deref().or(preset.and_then(|preset| preset.footer_template));
+    let footer = footer.map(Template::parse).transpose()?;
+    Ok(Layout { format, file_sep, header, footer })
+}
+
+/// Prints the presets and the options each stands for.
+pub fn run() -> io::Result<()> {
+    for preset in PRESETS {
+        println!("{}", preset.name);
+        println!("    {}", preset.description);
+        let format = preset.format.to_possible_value().expect("formats are never skipped");
+        let mut options =
+            format!("--format {} -f '{}' --header-template '{}'", format.get_name(), preset.file_sep, preset.header_template);
+        if let Some(footer) = preset.footer_template {
+            options.push_str(&format!(" --footer-template '{}'", footer));
+        }
+        println!("    {}", options);
+    }
+    Ok(())
+}
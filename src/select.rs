@@ -0,0 +1,183 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use ignore::overrides::{Override, OverrideBuilder};
+use ignore::WalkBuilder;
+
+use crate::git::{self, Snapshot};
+use crate::manifest::Selection;
+use crate::FileBundle;
+
+/// What walking the source directory depends on: the globs and the ignore
+/// settings, from the command line or from the selection of a manifest.
+#[derive(Debug, Clone, Copy)]
+pub struct WalkOptions<'a> {
+    pub src_dir: &'a Path,
+    pub src_globs: &'a [String],
+    pub respect_gitignore: bool,
+    pub respect_ignore_files: bool,
+    pub custom_ignore_file: &'a [String],
+}
+
+impl<'a> WalkOptions<'a> {
+    pub fn of(args: &'a FileBundle) -> Self {
+        WalkOptions {
+            src_dir: &args.src_dir,
+            src_globs: &args.src_globs,
+            respect_gitignore: args.respect_gitignore,
+            respect_ignore_files: args.respect_ignore_files,
+            custom_ignore_file: &args.custom_ignore_file,
+        }
+    }
+
+    /// The walk a manifest's selection recorded, repeated in `src_dir`.
+    pub fn recorded(selection: &'a Selection, src_dir: &'a Path) -> Self {
+        WalkOptions {
+            src_dir,
+            src_globs: &selection.src_globs,
+            respect_gitignore: selection.respect_gitignore,
+            respect_ignore_files: selection.respect_ignore_files,
+            custom_ignore_file: &selection.custom_ignore_file,
+        }
+    }
+}
+
+/// Builds the matcher for the `--src-globs` patterns, rooted at the source directory.
+pub fn overrides(options: WalkOptions) -> io::Result<Override> {
+    let mut override_builder = OverrideBuilder::new(options.src_dir);
+    for pattern in options.src_globs {
+        override_builder.add(pattern).map_err(io::Error::other)?;
+    }
+    override_builder.build().map_err(io::Error::other)
+}
+
+/// Walks the source directory for files matching the globs, honoring the
+/// ignore files the user asked for.
+pub fn walk(options: WalkOptions, overrides: Override) -> Vec<PathBuf> {
+    let mut walk_builder = walker(options);
+    // Overrides given to the walker would take precedence over ignore files, so
+    // apply the globs as a filter that only sees what the ignore rules let through.
+    walk_builder.filter_entry(move |entry| {
+        let is_dir = entry.file_type().is_some_and(|ft| ft.is_dir());
+        !overrides.matched(entry.path(), is_dir).is_ignore()
+    });
+    files(walk_builder)
+}
+
+/// Walks the source directory for every file the ignore rules let through,
+/// whether or not it matches the globs.
+pub fn walk_all(options: WalkOptions) -> Vec<PathBuf> {
+    files(walker(options))
+}
+
+fn walker(options: WalkOptions) -> WalkBuilder {
+    let mut walk_builder = WalkBuilder::new(options.src_dir);
+    walk_builder
+        .hidden(false)
+        .ignore(options.respect_ignore_files)
+        .git_ignore(options.respect_gitignore)
+        .git_global(options.respect_gitignore)
+        .git_exclude(options.respect_gitignore)
+        .require_git(false);
+    for name in options.custom_ignore_file {
+        walk_builder.add_custom_ignore_filename(name);
+    }
+    walk_builder
+}
+
+fn files(walk_builder: WalkBuilder) -> Vec<PathBuf> {
+    walk_builder
+        .build()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_some_and(|ft| ft.is_file()))
+        .map(|entry| entry.into_path())
+        .collect()
+}
+
+/// Reads an explicit list of files from `list`, or from stdin if it is `-`.
+///
+/// Paths are separated by NUL bytes if there are any (as written by
+/// `git ls-files -z` or `fd -0`), and by newlines otherwise. Paths that do not
+/// match the globs are dropped, as are paths that are not regular files.
+pub fn files_from(args: &FileBundle, list: &Path, overrides: &Override) -> io::Result<Vec<PathBuf>> {
+    let mut bytes = Vec::new();
+    if list.as_os_str() == "-" {
+        io::stdin().lock().read_to_end(&mut bytes)?;
+    } else {
+        bytes = fs::read(list)?;
+    }
+    let text = String::from_utf8(bytes)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "file list is not valid UTF-8"))?;
+
+    let separator = if text.contains('\0') { '\0' } else { '\n' };
+    let paths = text
+        .split(separator)
+        .map(|line| if separator == '\n' { line.trim_end_matches('\r') } else { line })
+        .filter(|line| !line.is_empty())
+        .map(PathBuf::from);
+    Ok(listed(args, paths, overrides))
+}
+
+/// Selects files by what git knows about them: the files picked by every one
+/// of `--git-tracked`, `--git-changed` and `--git-diff` that is given, and that
+/// match the globs.
+pub fn git_files(args: &FileBundle, overrides: &Override) -> io::Result<Vec<PathBuf>> {
+    let files = git_selection(args)?.unwrap_or_default();
+    Ok(listed(args, files.into_iter().map(|path| args.src_dir.join(path)), overrides))
+}
+
+/// Reads the files of `rev` that match the globs, and any `--git-*` selection,
+/// from the object database.
+pub fn git_snapshot(args: &FileBundle, rev: &str, overrides: &Override) -> io::Result<Snapshot> {
+    let selection: Option<HashSet<PathBuf>> = git_selection(args)?.map(|files| files.into_iter().collect());
+    Snapshot::extract(&args.src_dir, rev, |path| {
+        !overrides.matched(path, false).is_ignore() && selection.as_ref().is_none_or(|selection| selection.contains(path))
+    })
+}
+
+/// The paths, relative to the source directory, picked by every `--git-*`
+/// selection that is given, or `None` if there is none.
+fn git_selection(args: &FileBundle) -> io::Result<Option<Vec<PathBuf>>> {
+    let mut selections = Vec::new();
+    if args.git_tracked {
+        selections.push(git::tracked(&args.src_dir)?);
+    }
+    if args.git_changed {
+        selections.push(git::changed(&args.src_dir)?);
+    }
+    if let Some(range) = &args.git_diff {
+        selections.push(git::diff(&args.src_dir, range)?);
+    }
+
+    let mut selections = selections.into_iter();
+    let Some(mut files) = selections.next() else {
+        return Ok(None);
+    };
+    for selection in selections {
+        let selection: HashSet<PathBuf> = selection.into_iter().collect();
+        files.retain(|path| selection.contains(path));
+    }
+    Ok(Some(files))
+}
+
+/// Keeps the listed paths that match the globs and are regular files.
+fn listed(args: &FileBundle, paths: impl Iterator<Item = PathBuf>, overrides: &Override) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for path in paths {
+        if overrides.matched(&path, false).is_ignore() {
+            continue;
+        }
+        match fs::metadata(&path) {
+            Ok(meta) if meta.is_file() => files.push(path),
+            Ok(_) => {
+                if args.verbose {
+                    status!(args, "Skipping {}: not a file", path.display());
+                }
+            }
+            Err(err) => eprintln!("Warning: Skipping {}: {}", path.display(), err),
+        }
+    }
+    files
+}
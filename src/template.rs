@@ -0,0 +1,273 @@
+use std::io;
+
+/// A value that can appear in a header or footer template.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    /// The file separator
+    Sep,
+    /// The path, followed by the encoding for files that are not UTF-8 text
+    Path,
+    /// Number of lines in the block
+    Lines,
+    /// Size of the file in bytes
+    Size,
+    /// SHA-256 of the file, in hex
+    Sha256,
+    /// Position of the block in the bundle, from 1
+    Index,
+    /// Number of blocks in the bundle
+    Count,
+    /// Extension of the file, without the dot
+    Ext,
+}
+
+const FIELDS: &[(&str, Field)] = &[
+    ("sep", Field::Sep),
+    ("path", Field::Path),
+    ("lines", Field::Lines),
+    ("size", Field::Size),
+    ("sha256", Field::Sha256),
+    ("index", Field::Index),
+    ("count", Field::Count),
+    ("ext", Field::Ext),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Piece {
+    Text(String),
+    Field(Field),
+}
+
+/// A template such as `{sep} {path} ({lines} lines, {size} bytes)`. Header
+/// and footer templates are one line; separators may span several. `{{` and
+/// `}}` stand for literal braces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    pieces: Vec<Piece>,
+}
+
+/// The header used when no `--header-template` is given.
+pub const DEFAULT_HEADER: &str = "{sep} {path}";
+
+impl Template {
+    pub fn parse(template: &str) -> io::Result<Self> {
+        if template.contains('\n') {
+            return Err(invalid(template, "must be a single line"));
+        }
+        Self::parse_pieces(template)
+    }
+
+    /// Parses a `--file-sep`: escape sequences such as `\n`, `\t`, `\\`,
+    /// `\x41` and `\u{1F4C4}` are turned into the characters they stand for
+    /// first, so the separator may span several lines. It may contain every
+    /// placeholder except `{sep}`.
+    pub fn separator(file_sep: &str) -> io::Result<Self> {
+        let text = unescape(file_sep).map_err(|message| invalid(file_sep, &message))?;
+        let separator = Self::parse_pieces(&text).map_err(|err| invalid(file_sep, &err.to_string()))?;
+        if separator.uses(Field::Sep) {
+            return Err(invalid(file_sep, "a separator cannot contain {sep}"));
+        }
+        Ok(separator)
+    }
+
+    fn parse_pieces(template: &str) -> io::Result<Self> {
+        let invalid = |message: String| invalid(template, &message);
+        let mut pieces = Vec::new();
+        let mut text = String::new();
+        let mut rest = template;
+        while let Some(c) = rest.chars().next() {
+            if let Some(after) = rest.strip_prefix("{{").or_else(|| rest.strip_prefix("}}")) {
+                text.push(c);
+                rest = after;
+            } else if c == '{' {
+                let (name, after) = rest[1..].split_once('}').ok_or_else(|| invalid("unclosed '{'".to_string()))?;
+                let field = FIELDS.iter().find(|(known, _)| *known == name).map(|&(_, field)| field).ok_or_else(|| {
+                    let known: Vec<String> = FIELDS.iter().map(|(name, _)| format!("{{{}}}", name)).collect();
+                    invalid(format!("unknown placeholder {{{}}} (known: {})", name, known.join(", ")))
+                })?;
+                if !text.is_empty() {
+                    pieces.push(Piece::Text(std::mem::take(&mut text)));
+                }
+                pieces.push(Piece::Field(field));
+                rest = after;
+            } else if c == '}' {
+                return Err(invalid("unmatched '}'".to_string()));
+            } else {
+                text.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+        if !text.is_empty() {
+            pieces.push(Piece::Text(text));
+        }
+        Ok(Template { pieces })
+    }
+
+    pub fn uses(&self, field: Field) -> bool {
+        self.pieces.contains(&Piece::Field(field))
+    }
+
+    /// Whether the template, rendered with `sep`, contains `field`.
+    pub fn uses_with(&self, field: Field, sep: &Template) -> bool {
+        self.uses(field) || (self.uses(Field::Sep) && sep.uses(field))
+    }
+
+    /// How many lines the template spans when rendered with `sep`.
+    pub fn lines(&self, sep: &Template) -> usize {
+        let newlines = |template: &Template| -> usize {
+            template.pieces.iter().map(|piece| if let Piece::Text(text) = piece { text.matches('\n').count() } else { 0 }).sum()
+        };
+        let seps = self.pieces.iter().filter(|&piece| *piece == Piece::Field(Field::Sep)).count();
+        1 + newlines(self) + seps * newlines(sep)
+    }
+
+    /// The last word of literal text in the template, which `lengthen` makes longer.
+    pub fn marker(&self) -> Option<&str> {
+        self.pieces.iter().rev().find_map(|piece| match piece {
+            Piece::Text(text) => text.split_whitespace().last(),
+            Piece::Field(_) => None,
+        })
+    }
+
+    /// Repeats the last character of `marker`, e.g. to turn `---` into `----`.
+    /// Returns false if the template has no literal text to lengthen.
+    pub fn lengthen(&mut self) -> bool {
+        for piece in self.pieces.iter_mut().rev() {
+            if let Piece::Text(text) = piece {
+                if let Some((at, c)) = text.char_indices().rev().find(|(_, c)| !c.is_whitespace()) {
+                    text.insert(at, c);
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// The template as it would be written on the command line, with braces
+    /// doubled and control characters and backslashes escaped.
+    pub fn source(&self) -> String {
+        let mut source = String::new();
+        for piece in &self.pieces {
+            match piece {
+                Piece::Field(field) => {
+                    let name = FIELDS.iter().find(|(_, known)| known == field).map_or("", |(name, _)| name);
+                    source.push_str(&format!("{{{}}}", name));
+                }
+                Piece::Text(text) => {
+                    for c in text.chars() {
+                        match c {
+                            '{' => source.push_str("{{"),
+                            '}' => source.push_str("}}"),
+                            '\\' => source.push_str("\\\\"),
+                            '\n' => source.push_str("\\n"),
+                            '\t' => source.push_str("\\t"),
+                            '\r' => source.push_str("\\r"),
+                            c if c.is_control() => source.push_str(&format!("\\u{{{:x}}}", c as u32)),
+                            c => source.push(c),
+                        }
+                    }
+                }
+            }
+        }
+        source
+    }
+
+    pub fn render(&self, value: impl Fn(Field) -> String) -> String {
+        self.pieces
+            .iter()
+            .map(|piece| match piece {
+                Piece::Text(text) => text.clone(),
+                Piece::Field(field) => value(*field),
+            })
+            .collect()
+    }
+
+    /// Matches `line` against the template, as rendered with `sep`; `line`
+    /// holds as many lines as the template spans, without the last newline.
+    /// Returns the `{path}` if the template or separator has one, or an empty string.
+    pub fn parse_line<'l>(&self, line: &'l str, sep: &Template) -> Option<&'l str> {
+        let pieces: Vec<Piece> = self
+            .pieces
+            .iter()
+            .flat_map(|piece| match piece {
+                Piece::Field(Field::Sep) => sep.pieces.clone(),
+                piece => vec![piece.clone()],
+            })
+            .collect();
+        match_pieces(&pieces, line)
+    }
+}
+
+fn invalid(template: &str, message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("template {:?}: {}", template, message))
+}
+
+/// Turns the escape sequences of a `--file-sep` into the characters they stand for.
+fn unescape(text: &str) -> Result<String, String> {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        let escaped = match chars.next() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('0') => '\0',
+            Some('\\') => '\\',
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                u8::from_str_radix(&hex, 16)
+                    .ok()
+                    .filter(|&byte| hex.len() == 2 && byte.is_ascii())
+                    .map(char::from)
+                    .ok_or_else(|| format!("\\x{} is not an ASCII character (\\x00 to \\x7f)", hex))?
+            }
+            Some('u') => {
+                let rest = chars.as_str();
+                let code = rest.strip_prefix('{').and_then(|rest| rest.split_once('}')).map(|(code, _)| code);
+                let c = code
+                    .filter(|code| (1..=6).contains(&code.len()))
+                    .and_then(|code| u32::from_str_radix(code, 16).ok())
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| "\\u must be followed by a character code such as {1F4C4}".to_string())?;
+                chars = rest[code.map_or(0, str::len) + 2..].chars();
+                c
+            }
+            Some(other) => return Err(format!("unknown escape sequence \\{} (write \\\\ for a backslash)", other)),
+            None => return Err("ends in a lone backslash (write \\\\ for a backslash)".to_string()),
+        };
+        unescaped.push(escaped);
+    }
+    Ok(unescaped)
+}
+
+/// Matches `line` against `pieces`, trying the shortest `{path}` first and
+/// backtracking, so that a path with an encoding annotation such as
+/// `img/a.png (base64)` still matches `{path} ({size} bytes)`.
+fn match_pieces<'l>(pieces: &[Piece], line: &'l str) -> Option<&'l str> {
+    let Some((piece, rest)) = pieces.split_first() else {
+        return line.is_empty().then_some("");
+    };
+    let literal = |text: &str| line.strip_prefix(text).and_then(|line| match_pieces(rest, line));
+    let run = |accept: fn(char) -> bool, min: usize, max: usize| {
+        let len = line.find(|c: char| !accept(c)).unwrap_or(line.len());
+        (len >= min && len <= max).then(|| match_pieces(rest, &line[len..])).flatten()
+    };
+    // The shortest text within the line that lets the rest match.
+    let shortest = |min: usize| {
+        let limit = line.find('\n').unwrap_or(line.len());
+        (min..=limit).filter(|&end| line.is_char_boundary(end)).find_map(|end| Some((end, match_pieces(rest, &line[end..])?)))
+    };
+    match piece {
+        Piece::Text(text) => literal(text),
+        // Separators are expanded by `parse_line`.
+        Piece::Field(Field::Sep) => None,
+        Piece::Field(Field::Lines | Field::Size | Field::Index | Field::Count) => run(|c| c.is_ascii_digit(), 1, usize::MAX),
+        Piece::Field(Field::Sha256) => run(|c| c.is_ascii_hexdigit(), 64, 64),
+        Piece::Field(Field::Ext) => shortest(0).map(|(_, path)| path),
+        Piece::Field(Field::Path) => shortest(1).map(|(end, _)| &line[..end]),
+    }
+}
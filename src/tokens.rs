@@ -0,0 +1,35 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+
+/// How LLM tokens are counted for `--max-tokens` and `--count-tokens`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Tokenizer {
+    /// One token per four characters: fast, and close enough for English text and code
+    #[default]
+    Chars,
+    /// The BPE vocabulary of GPT-4 and GPT-3.5 (cl100k_base)
+    Cl100k,
+    /// The BPE vocabulary of GPT-4o and later OpenAI models (o200k_base)
+    O200k,
+}
+
+impl Tokenizer {
+    pub fn name(self) -> &'static str {
+        match self {
+            Tokenizer::Chars => "chars",
+            Tokenizer::Cl100k => "cl100k",
+            Tokenizer::O200k => "o200k",
+        }
+    }
+
+    /// The number of tokens in `text`. The BPE vocabularies are compiled into
+    /// the binary and loaded on first use.
+    pub fn count(self, text: &str) -> usize {
+        match self {
+            Tokenizer::Chars => text.chars().count().div_ceil(4),
+            Tokenizer::Cl100k => tiktoken_rs::cl100k_base_singleton().encode_ordinary(text).len(),
+            Tokenizer::O200k => tiktoken_rs::o200k_base_singleton().encode_ordinary(text).len(),
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::content::Source;
+use crate::format;
+
+/// What `--tree` shows next to each file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum TreeInfo {
+    /// Size of the file on disk
+    Size,
+    /// Number of lines of text files
+    Lines,
+    /// Number of tokens the file adds to the bundle
+    Tokens,
+}
+
+/// Marks files that are present but not in the bundle.
+const EXCLUDED: &str = "[excluded]";
+
+#[derive(Debug, Default)]
+struct Node {
+    children: BTreeMap<String, Node>,
+    /// Set for files: the annotation and whether the file is in the bundle.
+    file: Option<(String, bool)>,
+}
+
+impl Node {
+    fn insert(&mut self, path: &[String], note: String, included: bool) {
+        let node = path.iter().fold(self, |node, name| node.children.entry(name.clone()).or_default());
+        node.file = Some((note, included));
+    }
+
+    fn is_excluded(&self) -> bool {
+        match &self.file {
+            Some((_, included)) => !included,
+            None => self.children.values().all(Node::is_excluded),
+        }
+    }
+
+    fn render(&self, prefix: &str, out: &mut String) {
+        let count = self.children.len();
+        for (i, (name, child)) in self.children.iter().enumerate() {
+            let last = i + 1 == count;
+            out.push_str(prefix);
+            out.push_str(if last { "`-- " } else { "|-- " });
+            out.push_str(name);
+            match &child.file {
+                Some((note, included)) => {
+                    if !note.is_empty() {
+                        out.push_str(&format!("  ({})", note));
+                    }
+                    if !included {
+                        out.push_str(&format!("  {}", EXCLUDED));
+                    }
+                    out.push('\n');
+                }
+                // A directory with nothing in the bundle is shown once, without its contents.
+                None if child.is_excluded() => out.push_str(&format!("/  {}\n", EXCLUDED)),
+                None => {
+                    out.push_str("/\n");
+                    child.render(&format!("{}{}", prefix, if last { "    " } else { "|   " }), out);
+                }
+            }
+        }
+    }
+}
+
+/// Renders an ASCII tree, like `tree --charset ascii`, of the files in
+/// `sources` below `root`, headed by `title`. `tokens` holds the token count of each source if
+/// they were counted. Files in `present` that are not in `sources` are listed
+/// as excluded.
+pub fn render(root: &Path, title: &str, sources: &[Source], tokens: &[usize], info: &[TreeInfo], present: &[PathBuf]) -> String {
+    // `./src/main.rs` and `src/main.rs` are the same file.
+    let relative = |path: &Path| -> Vec<String> {
+        path.strip_prefix(root)
+            .unwrap_or(path)
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+                _ => None,
+            })
+            .collect()
+    };
+
+    let mut tree = Node::default();
+    for (i, source) in sources.iter().enumerate() {
+        let notes: Vec<String> = info
+            .iter()
+            .filter_map(|info| match info {
+                TreeInfo::Size => Some(human_size(source.size)),
+                TreeInfo::Lines if !format::is_binary(source.encoding) => Some(format!("{} lines", source.stats.lines)),
+                TreeInfo::Lines => None,
+                TreeInfo::Tokens => tokens.get(i).map(|tokens| format!("{} tokens", tokens)),
+            })
+            .collect();
+        tree.insert(&relative(&source.path), notes.join(", "), true);
+    }
+
+    let included: HashSet<Vec<String>> = sources.iter().map(|source| relative(&source.path)).collect();
+    for path in present.iter().map(|path| relative(path)).filter(|path| !included.contains(path)) {
+        tree.insert(&path, String::new(), false);
+    }
+
+    let mut out = format!("{}\n", title);
+    tree.render("", &mut out);
+    out
+}
+
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
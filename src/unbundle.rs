@@ -0,0 +1,318 @@
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use clap::Args;
+
+use crate::collision::Markers;
+use crate::content;
+use crate::format::{self, Format};
+use crate::manifest;
+use crate::output::CONTINUED;
+use crate::preset::{self, LayoutArgs};
+use crate::verify;
+
+/// Arguments for `fbundle unbundle`.
+#[derive(Debug, Args)]
+pub struct UnbundleArgs {
+    /// The bundle file to unpack, or all parts of a split bundle in order
+    #[arg(required = true)]
+    pub bundles: Vec<PathBuf>,
+
+    /// Directory to recreate the files in
+    #[arg(short = 'o', long, default_value = ".")]
+    pub out_dir: PathBuf,
+
+    #[command(flatten)]
+    pub layout: LayoutArgs,
+
+    /// List the files that would be written without touching the disk
+    #[arg(long, default_value_t = false)]
+    pub dry_run: bool,
+
+    #[arg(short = 'v', long, default_value_t = false)]
+    pub verbose: bool,
+}
+
+/// A single file block read back from a bundle.
+#[derive(Debug)]
+pub struct Entry {
+    pub path: String,
+    /// The encoding from the header, `format::TEXT` if it had none.
+    pub encoding: String,
+    pub contents: String,
+}
+
+impl Entry {
+    fn new(header: &str, contents: &str) -> Self {
+        let (path, encoding) = format::parse_header_path(header);
+        Entry { path: path.to_string(), encoding: encoding.to_string(), contents: contents.to_string() }
+    }
+
+    /// The bytes to write for this entry, or `None` for placeholders of
+    /// binary files that were not embedded and for diffs.
+    pub fn bytes(&self) -> io::Result<Option<Vec<u8>>> {
+        content::decode(&self.encoding, &self.contents)
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", self.path, err)))
+    }
+}
+
+/// Splits a bundle into its file blocks.
+///
+/// Each block is written as `"{header}\n{contents}\n"`, or with a footer as
+/// `"{header}\n{contents}\n{footer}\n"`, so a block ends where the next lines
+/// matching the header template begin, and the newline in front of them is
+/// not part of the contents. A separator with newlines makes headers and
+/// footers span several lines. Anything in front of the first header, such as
+/// a `--tree` overview, is skipped. Markdown blocks have an empty line and a
+/// fenced code block instead, which is taken apart again. With `unescape`,
+/// lines escaped by `--sep-collision escape` are restored.
+pub fn parse(bundle: &str, markers: &Markers, format: Format, unescape: bool) -> io::Result<Vec<Entry>> {
+    let Markers { file_sep, header, footer } = *markers;
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+
+    // Where every line starts, and the end of the bundle.
+    let mut starts: Vec<usize> = bundle.split_inclusive('\n').scan(0, |offset, line| {
+        let start = *offset;
+        *offset += line.len();
+        Some(start)
+    }).collect();
+    starts.push(bundle.len());
+
+    // (start of the header, start of the contents, path)
+    let mut headers = Vec::new();
+    let span = header.lines(file_sep);
+    let mut line = 0;
+    while line + span < starts.len() {
+        let text = &bundle[starts[line]..starts[line + span]];
+        if let Some(path) = header.parse_line(text.strip_suffix('\n').unwrap_or(text), file_sep) {
+            if !text.ends_with('\n') {
+                return Err(invalid("bundle ends inside a file header".to_string()));
+            }
+            headers.push((starts[line], starts[line + span], path));
+            line += span;
+            if format == Format::Markdown {
+                line = skip_fenced_block(bundle, &starts, line, footer.map_or(0, |footer| footer.lines(file_sep)));
+            }
+        } else {
+            line += 1;
+        }
+    }
+    if headers.is_empty() && !bundle.is_empty() {
+        return Err(invalid("no line of the bundle is a header written with the given file separator".to_string()));
+    }
+
+    let mut entries = Vec::with_capacity(headers.len());
+    for (i, &(_, start, path)) in headers.iter().enumerate() {
+        let end = headers.get(i + 1).map_or(bundle.len(), |&(next, _, _)| next);
+        let block = &bundle[start..end];
+        let mut contents = block.strip_suffix('\n').unwrap_or(block);
+        if format == Format::Markdown {
+            // The footer follows the closing fence, and an empty line follows the block.
+            contents = contents.strip_suffix('\n').unwrap_or(contents);
+        }
+        if let Some(footer) = footer {
+            let (body, last) = split_last_lines(contents, footer.lines(file_sep));
+            if footer.parse_line(last, file_sep).is_none() {
+                return Err(invalid(format!("{}: block does not end with a footer", path)));
+            }
+            contents = body;
+        }
+        if format == Format::Markdown {
+            contents = unfence(contents).ok_or_else(|| {
+                invalid(format!("{}: block is not a fenced code block (use --format plain for plain bundles)", path))
+            })?;
+        }
+        let contents = if unescape { markers.unescape(contents) } else { contents.into() };
+        entries.push(Entry::new(path, &contents));
+    }
+    Ok(entries)
+}
+
+/// The line after the Markdown block whose header ends before line `line`:
+/// after its closing fence and `footer` lines, so that nothing in the code
+/// block is taken for a header. Blocks that are not fenced are left to `unfence`.
+fn skip_fenced_block(bundle: &str, starts: &[usize], line: usize, footer: usize) -> usize {
+    let text = |line: usize| bundle[starts[line]..starts[line + 1]].trim_end_matches('\n');
+    if line + 2 >= starts.len() || !text(line).is_empty() {
+        return line;
+    }
+    let opening = text(line + 1);
+    let fence = &opening[..opening.find(|c| c != '`').unwrap_or(opening.len())];
+    if fence.len() < 3 {
+        return line;
+    }
+    match (line + 2..starts.len() - 1).find(|&closing| text(closing) == fence) {
+        Some(closing) => (closing + 1 + footer).min(starts.len() - 1),
+        None => line,
+    }
+}
+
+/// The contents of a Markdown block: `"\n{fence}{language}\n{contents}{fence}"`,
+/// with a newline added to contents that did not end in one, so a file that
+/// did not end in a newline comes back with one.
+fn unfence(block: &str) -> Option<&str> {
+    let (opening, rest) = block.strip_prefix('\n')?.split_once('\n').unwrap_or((block.get(1..)?, ""));
+    let fence = &opening[..opening.find(|c| c != '`').unwrap_or(opening.len())];
+    if fence.len() < 3 {
+        return None;
+    }
+    let contents = rest.strip_suffix(fence)?;
+    (contents.is_empty() || contents.ends_with('\n')).then_some(contents)
+}
+
+/// Splits the last `count` lines off `text`, dropping the newline in front of them.
+fn split_last_lines(text: &str, count: usize) -> (&str, &str) {
+    let mut split = text.len();
+    for _ in 0..count {
+        match text[..split].rfind('\n') {
+            Some(newline) => split = newline,
+            None => return ("", text),
+        }
+    }
+    (&text[..split], &text[split + 1..])
+}
+
+/// Joins the chunks of files that were split across bundle parts back into
+/// a single entry.
+pub fn join_continued(entries: Vec<Entry>) -> Vec<Entry> {
+    let mut joined: Vec<Entry> = Vec::with_capacity(entries.len());
+    for entry in entries {
+        if let (Some(path), Some(previous)) = (entry.path.strip_suffix(CONTINUED), joined.last_mut()) {
+            if previous.path == path {
+                previous.contents.push_str(&entry.contents);
+                continue;
+            }
+        }
+        joined.push(entry);
+    }
+    joined
+}
+
+/// Takes the `<rev>:` that `--git-rev` puts in front of every path off the
+/// paths of `entries`.
+pub fn strip_rev(entries: &mut [Entry], rev: Option<&str>) -> io::Result<()> {
+    let Some(rev) = rev else {
+        return Ok(());
+    };
+    let prefix = format!("{}:", rev);
+    for entry in entries {
+        let Some(path) = entry.path.strip_prefix(&prefix) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: path is not labelled with the revision {}", entry.path, rev),
+            ));
+        };
+        entry.path = path.to_string();
+    }
+    Ok(())
+}
+
+/// Turns a header path into a path relative to the output directory,
+/// refusing anything absolute or containing `..`.
+pub fn safe_relative_path(path: &str) -> io::Result<PathBuf> {
+    let mut relative = PathBuf::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("refusing to write {}: path escapes the output directory", path),
+                ));
+            }
+        }
+    }
+    if relative.as_os_str().is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("invalid file path {:?}", path)));
+    }
+    Ok(relative)
+}
+
+/// Where `relative`, a path from `safe_relative_path`, is written below `dir`.
+/// Refuses it if a symbolic link on the way leads out of `dir`, or if the file
+/// itself is a symbolic link, which writing would follow. Nothing is created,
+/// so that every entry can be checked before anything is written.
+pub fn target_path(dir: &Path, relative: &Path, label: &str) -> io::Result<PathBuf> {
+    let refuse = |reason: String| io::Error::new(io::ErrorKind::InvalidData, format!("refusing to write {}: {}", label, reason));
+    let root = match dir.canonicalize() {
+        Ok(root) => root,
+        // Whatever is created in a new directory is inside it.
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(dir.join(relative)),
+        Err(err) => return Err(err),
+    };
+    let mut path = root.clone();
+    let mut components = relative.components().peekable();
+    while let Some(component) = components.next() {
+        path.push(component);
+        let meta = match fs::symlink_metadata(&path) {
+            Ok(meta) => meta,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => break,
+            Err(err) => return Err(err),
+        };
+        if !meta.file_type().is_symlink() {
+            continue;
+        }
+        if components.peek().is_none() {
+            return Err(refuse(format!("{} is a symbolic link", path.display())));
+        }
+        if !path.canonicalize()?.starts_with(&root) {
+            return Err(refuse(format!("path escapes {}", dir.display())));
+        }
+    }
+    Ok(root.join(relative))
+}
+
+pub fn run(args: UnbundleArgs) -> io::Result<()> {
+    let layout = preset::read_back(&args.layout, &args.bundles[0])?;
+    let markers = Markers { file_sep: &layout.file_sep, header: &layout.header, footer: layout.footer.as_ref() };
+
+    let mut entries = Vec::new();
+    for bundle in &args.bundles {
+        entries.extend(parse(&fs::read_to_string(bundle)?, &markers, layout.format, args.layout.unescape)?);
+    }
+    let entries = join_continued(entries);
+    // An embedded manifest describes the bundle; it is not one of its files.
+    let mut entries: Vec<Entry> = entries.into_iter().filter(|entry| entry.path != manifest::EMBEDDED_PATH).collect();
+    let rev = match &args.layout.git_rev {
+        Some(rev) => Some(rev.clone()),
+        None => verify::load(&args.bundles).ok().and_then(|manifest| manifest.selection.git_rev),
+    };
+    strip_rev(&mut entries, rev.as_deref())?;
+
+    // Validate every path and body up front so a bad entry leaves nothing half-written.
+    let mut files = Vec::with_capacity(entries.len());
+    for entry in &entries {
+        let target = safe_relative_path(&entry.path)?;
+        let out_path = target_path(&args.out_dir, &target, &entry.path)?;
+        match entry.bytes()? {
+            Some(bytes) => files.push((target, out_path, bytes)),
+            None if entry.encoding == format::DIFF => {
+                eprintln!("Warning: {} is a diff, not the contents of the file. Skipping.", entry.path)
+            }
+            None => eprintln!("Warning: {} is a placeholder for a binary file. Skipping.", entry.path),
+        }
+    }
+
+    if args.dry_run {
+        for (target, _, bytes) in &files {
+            println!("{} ({} bytes)", args.out_dir.join(target).display(), bytes.len());
+        }
+        println!("Would write {} files to: {}", files.len(), args.out_dir.display());
+        return Ok(());
+    }
+
+    for (_, out_path, bytes) in &files {
+        if let Some(parent) = out_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if args.verbose {
+            println!("Writing file: {}", out_path.display());
+        }
+        fs::write(out_path, bytes)?;
+    }
+
+    println!("Unbundled {} files into: {}", files.len(), args.out_dir.display());
+    Ok(())
+}
@@ -0,0 +1,185 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use clap::{Args, ValueEnum};
+use rayon::prelude::*;
+
+use crate::format::{self, Format, PathStyle};
+use crate::manifest::{FileEntry, Manifest};
+use crate::output::OutputFiles;
+use crate::select::{self, WalkOptions};
+
+/// Arguments for `fbundle verify`.
+#[derive(Debug, Args)]
+pub struct VerifyArgs {
+    /// The bundle (any part or the index of a split bundle), or its sidecar manifest
+    #[arg(required = true)]
+    pub bundles: Vec<PathBuf>,
+
+    /// The source directory the bundle was made from
+    #[arg(short = 's', long, default_value = ".")]
+    pub src_dir: PathBuf,
+
+    /// Also list files that are unchanged
+    #[arg(short = 'v', long, default_value_t = false)]
+    pub verbose: bool,
+}
+
+/// Finds the manifest of a bundle: the file itself if it is a manifest, a
+/// sidecar next to it, or a manifest embedded in it.
+pub fn load(bundles: &[PathBuf]) -> io::Result<Manifest> {
+    let read = |path: &Path| -> io::Result<Manifest> {
+        serde_json::from_str(&fs::read_to_string(path)?)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), err)))
+    };
+
+    for bundle in bundles {
+        let name = bundle.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        if name.ends_with(".manifest.json") {
+            return read(bundle);
+        }
+        let sidecar = bundle.with_file_name(format!("{}.manifest.json", bundle_name(bundle)));
+        if sidecar.is_file() {
+            return read(&sidecar);
+        }
+    }
+
+    // The index of a split bundle lists the parts, the last of which holds the manifest.
+    let mut parts = Vec::new();
+    for bundle in bundles {
+        let is_index = bundle.file_stem().is_some_and(|stem| stem.to_string_lossy().ends_with(".index"));
+        if !is_index {
+            parts.push(bundle.clone());
+            continue;
+        }
+        for line in fs::read_to_string(bundle)?.lines() {
+            let part = bundle.with_file_name(line.split('\t').next().unwrap_or_default());
+            if parts.last() != Some(&part) {
+                parts.push(part);
+            }
+        }
+    }
+    for part in parts.iter().rev() {
+        if let Some(manifest) = Manifest::find_embedded(&fs::read_to_string(part)?) {
+            return Ok(manifest);
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::NotFound, "no sidecar or embedded manifest found for the bundle"))
+}
+
+/// The name the bundle was created with: file_bundle.txt, file_bundle.002.txt,
+/// file_bundle.index.txt and file_bundle.manifest.json all belong to `file_bundle`.
+fn bundle_name(bundle: &Path) -> String {
+    let name = bundle.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    if let Some(name) = name.strip_suffix(".manifest.json") {
+        return name.to_string();
+    }
+    let stem = bundle.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    match stem.rsplit_once('.') {
+        Some((stem, part)) if part == "index" || (part.len() == 3 && part.bytes().all(|b| b.is_ascii_digit())) => stem.to_string(),
+        _ => stem,
+    }
+}
+
+/// The files written for the bundles, which the bundle left out of its own
+/// input. The extension of the parts is not known from an index or a
+/// manifest, so every format's default extension is tried as well.
+fn output_files(bundles: &[PathBuf]) -> Vec<OutputFiles> {
+    let mut outputs = Vec::new();
+    for bundle in bundles {
+        let dir = match bundle.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let name = bundle_name(bundle);
+        let own = bundle.extension().map(|extension| format!(".{}", extension.to_string_lossy()));
+        let defaults = Format::value_variants().iter().map(|format| format.default_extension().to_string());
+        for extension in own.into_iter().chain(defaults) {
+            outputs.push(OutputFiles::new(dir, &name, &extension));
+        }
+    }
+    outputs
+}
+
+/// Lists the files the bundle's selection would pick in `src_dir` today,
+/// relative to `src_dir`, leaving out the files of the bundle itself.
+fn current_files(manifest: &Manifest, src_dir: &Path, outputs: &[OutputFiles]) -> io::Result<BTreeSet<String>> {
+    let options = WalkOptions::recorded(&manifest.selection, src_dir);
+    let overrides = select::overrides(options)?;
+    Ok(select::walk(options, overrides)
+        .iter()
+        .filter(|path| !outputs.iter().any(|outputs| outputs.contains(path)))
+        .map(|path| format::label(path, src_dir, PathStyle::Relative))
+        .collect())
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Change {
+    Unchanged,
+    Added,
+    Removed,
+    Modified,
+    /// The contents are the same but the permissions differ.
+    Mode(Option<String>, Option<String>),
+}
+
+pub fn run(args: VerifyArgs) -> io::Result<()> {
+    let manifest = load(&args.bundles)?;
+
+    let checked = manifest
+        .files
+        .par_iter()
+        .map(|recorded| {
+            let path = args.src_dir.join(&recorded.path);
+            match FileEntry::of(&path, recorded.path.clone(), recorded.bundled) {
+                Ok(current) => Ok((recorded, Some(current))),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => Ok((recorded, None)),
+                Err(err) => Err(err),
+            }
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let mut report = BTreeMap::new();
+    for (recorded, current) in &checked {
+        let change = match current {
+            None => Change::Removed,
+            Some(current) if current.sha256 != recorded.sha256 || current.size != recorded.size => Change::Modified,
+            Some(current) if current.mode != recorded.mode => Change::Mode(recorded.mode.clone(), current.mode.clone()),
+            Some(_) => Change::Unchanged,
+        };
+        report.insert(recorded.path.clone(), change);
+    }
+
+    // Files listed with --files-from cannot have been added by walking.
+    if !manifest.selection.files_from {
+        for path in current_files(&manifest, &args.src_dir, &output_files(&args.bundles))? {
+            report.entry(path).or_insert(Change::Added);
+        }
+    }
+
+    let mut differences = 0;
+    for (path, change) in &report {
+        let unknown = |mode: &Option<String>| mode.clone().unwrap_or_else(|| "?".to_string());
+        match change {
+            Change::Unchanged if !args.verbose => continue,
+            Change::Unchanged => println!("ok        {}", path),
+            Change::Added => println!("added     {}", path),
+            Change::Removed => println!("removed   {}", path),
+            Change::Modified => println!("modified  {}", path),
+            Change::Mode(old, new) => println!("mode      {} ({} -> {})", path, unknown(old), unknown(new)),
+        }
+        if *change != Change::Unchanged {
+            differences += 1;
+        }
+    }
+
+    if differences == 0 {
+        println!("{} matches {} ({} files)", args.bundles[0].display(), args.src_dir.display(), manifest.files.len());
+        Ok(())
+    } else {
+        println!("{} differences between {} and {}", differences, args.bundles[0].display(), args.src_dir.display());
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::BTreeSet;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+
+use ignore::overrides::Override;
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+
+use crate::output::OutputFiles;
+use crate::select;
+use crate::template::Template;
+use crate::FileBundle;
+
+/// How long the source directory has to be quiet before the bundle is
+/// rebuilt, so that a burst of events (an editor saving, a `git checkout`)
+/// causes a single rebuild.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Decides which changes in the source directory affect the bundle.
+struct Filter<'a> {
+    args: &'a FileBundle,
+    root: PathBuf,
+    overrides: Override,
+    files_from: Option<PathBuf>,
+    outputs: OutputFiles,
+}
+
+impl Filter<'_> {
+    fn is_ignore_file(&self, name: &str) -> bool {
+        (self.args.respect_gitignore && name == ".gitignore")
+            || (self.args.respect_ignore_files && name == ".ignore")
+            || self.args.custom_ignore_file.iter().any(|custom| custom == name)
+    }
+
+    fn affects_bundle(&self, path: &Path) -> bool {
+        if self.outputs.contains(path) {
+            return false;
+        }
+        if self.files_from.as_deref() == Some(path) {
+            return true;
+        }
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            return false;
+        };
+        if relative.file_name().is_some_and(|name| self.is_ignore_file(&name.to_string_lossy())) {
+            return true;
+        }
+        !self.overrides.matched(relative, path.is_dir()).is_ignore()
+    }
+}
+
+/// Writes the bundle, then rewrites it whenever files that match the globs
+/// change, until interrupted.
+pub fn run(args: &FileBundle, file_sep: &Template) -> io::Result<()> {
+    let root = args.src_dir.canonicalize()?;
+    let files_from = args.files_from.as_deref().map(Path::canonicalize).transpose()?;
+    let outputs = OutputFiles::new(&args.out_dir, &args.bundle_name, args.extension());
+    let filter = Filter { args, root, overrides: select::overrides(select::WalkOptions::of(args))?, files_from, outputs };
+
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender).map_err(io::Error::other)?;
+    watcher.watch(&filter.root, RecursiveMode::Recursive).map_err(io::Error::other)?;
+    if let Some(list) = filter.files_from.as_deref().filter(|list| !list.starts_with(&filter.root)) {
+        watcher.watch(list, RecursiveMode::NonRecursive).map_err(io::Error::other)?;
+    }
+
+    rebuild(args, file_sep);
+    status!(args, "Watching {} for changes (Ctrl-C to stop)", args.src_dir.display());
+
+    let changes = |event: notify::Result<Event>| -> Vec<PathBuf> {
+        match event {
+            Ok(event) if !matches!(event.kind, EventKind::Access(_)) => {
+                event.paths.into_iter().filter(|path| filter.affects_bundle(path)).collect()
+            }
+            Ok(_) => Vec::new(),
+            Err(err) => {
+                eprintln!("Warning: {}", err);
+                Vec::new()
+            }
+        }
+    };
+
+    while let Ok(event) = receiver.recv() {
+        let mut changed: BTreeSet<PathBuf> = changes(event).into_iter().collect();
+        if changed.is_empty() {
+            continue;
+        }
+        loop {
+            match receiver.recv_timeout(DEBOUNCE) {
+                Ok(event) => changed.extend(changes(event)),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+
+        if args.verbose {
+            for path in &changed {
+                status!(args, "Changed: {}", path.display());
+            }
+        }
+        rebuild(args, file_sep);
+    }
+    Ok(())
+}
+
+/// Writes the bundle, reporting rather than returning errors so that a
+/// failed rebuild, e.g. over `--max-tokens`, does not end the watch.
+fn rebuild(args: &FileBundle, file_sep: &Template) {
+    if let Err(err) = crate::bundle(args, file_sep) {
+        eprintln!("Error: {}", err);
+    }
+}
@@ -0,0 +1,84 @@
+use std::fs;
+
+mod common;
+use common::{run, scratch_dir};
+
+#[test]
+fn edited_bundle_is_written_back_unless_the_source_changed_since() {
+    let root = scratch_dir("apply-edited");
+    let src = root.join("src");
+    fs::create_dir_all(&src).unwrap();
+    fs::write(src.join("a.rs"), "one\ntwo\n").unwrap();
+    fs::write(src.join("b.rs"), "b\n").unwrap();
+    let made = run(&root, &["-s", "src", "-f", "---", "-g", "*", "--manifest", "sidecar", "--no-cache"]);
+    assert!(made.status.success());
+
+    let bundle = root.join("file_bundle.txt");
+    let edited = fs::read_to_string(&bundle).unwrap().replace("two\n", "TWO\n").replace("--- b.rs\nb\n\n", "");
+    fs::write(&bundle, edited + "--- c.rs\nc\n\n").unwrap();
+
+    // A file changed since the bundle was made is a conflict, and nothing is written.
+    fs::write(src.join("a.rs"), "one\ntwo, edited\n").unwrap();
+    let refused = run(&root, &["apply", "-f", "---", "file_bundle.txt", "-s", "src"]);
+    assert!(!refused.status.success());
+    assert!(String::from_utf8_lossy(&refused.stderr).contains("Conflict: a.rs changed since the bundle was created"));
+    assert!(!src.join("c.rs").exists());
+
+    fs::write(src.join("a.rs"), "one\ntwo\n").unwrap();
+    let applied = run(&root, &["apply", "-f", "---", "file_bundle.txt", "-s", "src", "--report-deleted"]);
+    assert!(applied.status.success(), "{}", String::from_utf8_lossy(&applied.stderr));
+    let stdout = String::from_utf8_lossy(&applied.stdout);
+    assert!(stdout.contains("--- a/a.rs\n+++ b/a.rs\n@@ -1,2 +1,2 @@\n one\n-two\n+TWO\n"));
+    assert!(stdout.contains("deleted    b.rs"));
+    assert_eq!(fs::read_to_string(src.join("a.rs")).unwrap(), "one\nTWO\n");
+    assert_eq!(fs::read_to_string(src.join("c.rs")).unwrap(), "c\n");
+    // Files deleted from the bundle are only reported.
+    assert_eq!(fs::read_to_string(src.join("b.rs")).unwrap(), "b\n");
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn markdown_bundles_are_applied_without_their_fences() {
+    let root = scratch_dir("apply-markdown");
+    let src = root.join("src");
+    fs::create_dir_all(&src).unwrap();
+    fs::write(src.join("a.rs"), "fn main() {}\n").unwrap();
+    fs::write(src.join("b.txt"), "no newline").unwrap();
+    let made = run(&root, &["-s", "src", "-g", "*", "--format", "markdown", "--manifest", "sidecar", "--no-cache"]);
+    assert!(made.status.success(), "{}", String::from_utf8_lossy(&made.stderr));
+
+    // Unedited, the bundle matches the source tree exactly.
+    let unchanged = run(&root, &["apply", "--format", "markdown", "file_bundle.md", "-s", "src"]);
+    assert!(unchanged.status.success(), "{}", String::from_utf8_lossy(&unchanged.stderr));
+    assert_eq!(String::from_utf8_lossy(&unchanged.stdout), "Applied 0 changed files to src (2 unchanged)\n");
+
+    let bundle = root.join("file_bundle.md");
+    fs::write(&bundle, fs::read_to_string(&bundle).unwrap().replace("fn main() {}", "fn main() { run() }")).unwrap();
+    let applied = run(&root, &["apply", "--format", "markdown", "file_bundle.md", "-s", "src"]);
+    assert!(applied.status.success(), "{}", String::from_utf8_lossy(&applied.stderr));
+    assert_eq!(fs::read_to_string(src.join("a.rs")).unwrap(), "fn main() { run() }\n");
+    assert_eq!(fs::read_to_string(src.join("b.txt")).unwrap(), "no newline");
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinks_out_of_the_source_directory_are_refused_before_anything_is_written() {
+    let root = scratch_dir("apply-symlink");
+    let src = root.join("src");
+    fs::create_dir_all(&src).unwrap();
+    fs::create_dir_all(root.join("elsewhere")).unwrap();
+    fs::write(src.join("a.rs"), "a\n").unwrap();
+    std::os::unix::fs::symlink(root.join("elsewhere"), src.join("link")).unwrap();
+    fs::write(root.join("file_bundle.txt"), "--- a.rs\nA\n\n--- link/deep/evil.rs\nboom\n").unwrap();
+
+    let refused = run(&root, &["apply", "-f", "---", "file_bundle.txt", "-s", "src"]);
+    assert!(!refused.status.success());
+    assert!(String::from_utf8_lossy(&refused.stderr).contains("refusing to write link/deep/evil.rs: path escapes src"));
+    assert!(!root.join("elsewhere/deep").exists());
+    assert_eq!(fs::read_to_string(src.join("a.rs")).unwrap(), "a\n");
+
+    fs::remove_dir_all(&root).unwrap();
+}
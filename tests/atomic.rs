@@ -0,0 +1,35 @@
+use std::fs;
+use std::path::Path;
+
+mod common;
+use common::{run, scratch_dir};
+
+fn temporaries(dir: &Path) -> Vec<String> {
+    fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name.ends_with(".tmp"))
+        .collect()
+}
+
+#[test]
+fn a_failed_run_leaves_no_temporary_files() {
+    let root = scratch_dir("atomic-failure");
+    fs::create_dir_all(root.join("src")).unwrap();
+    let big: String = (0..100).map(|i| format!("line {}\n", i)).collect();
+    fs::write(root.join("src/a.txt"), big).unwrap();
+
+    // A directory in the way makes renaming the finished files into place fail.
+    for (blocker, extra) in [("file_bundle.txt", &[][..]), ("file_bundle.index.txt", &["--max-bytes", "300"][..])] {
+        fs::create_dir_all(root.join(blocker)).unwrap();
+        fs::write(root.join(blocker).join("inside"), "kept").unwrap();
+        let args = [&["-s", "src", "-f", "---", "-g", "*", "--no-cache", "--manifest", "sidecar"][..], extra].concat();
+        let output = run(&root, &args);
+        assert!(!output.status.success(), "{}", String::from_utf8_lossy(&output.stdout));
+        assert_eq!(temporaries(&root), Vec::<String>::new());
+        assert_eq!(fs::read_to_string(root.join(blocker).join("inside")).unwrap(), "kept");
+        fs::remove_dir_all(root.join(blocker)).unwrap();
+    }
+
+    fs::remove_dir_all(&root).unwrap();
+}
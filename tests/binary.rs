@@ -0,0 +1,64 @@
+use std::fs;
+use std::path::PathBuf;
+
+mod common;
+use common::{fbundle, scratch_dir};
+
+/// Every byte value a few times over, so that encoded bodies wrap.
+fn binary() -> Vec<u8> {
+    (0..3).flat_map(|_| 0..=255u8).collect()
+}
+
+fn project(name: &str) -> PathBuf {
+    let root = scratch_dir(name);
+    fs::create_dir_all(root.join("src")).unwrap();
+    fs::write(root.join("src/a.txt"), "text\n").unwrap();
+    fs::write(root.join("src/blob.bin"), binary()).unwrap();
+    // Form feeds and the odd control character do not make a file binary.
+    fs::write(root.join("src/page.txt"), "one\x0ctwo\x1b[0m\n").unwrap();
+    root
+}
+
+#[test]
+fn base64_and_hex_bodies_unbundle_to_the_original_bytes() {
+    for policy in ["base64", "hex"] {
+        let root = project(&format!("binary-{}", policy));
+        fbundle(&root, &["-s", "src", "-f", "---", "-g", "*", "--no-cache", "--binary", policy]);
+        let bundle = fs::read_to_string(root.join("file_bundle.txt")).unwrap();
+        assert!(bundle.contains(&format!("--- blob.bin ({})\n", policy)), "{}", bundle);
+        assert!(bundle.lines().all(|line| line.len() <= 76), "{}", bundle);
+        assert!(bundle.contains("--- page.txt\none\x0ctwo\x1b[0m\n"), "{}", bundle);
+
+        fbundle(&root, &["unbundle", "-f", "---", "file_bundle.txt", "-o", "out"]);
+        assert_eq!(fs::read(root.join("out/blob.bin")).unwrap(), binary());
+        assert_eq!(fs::read_to_string(root.join("out/page.txt")).unwrap(), "one\x0ctwo\x1b[0m\n");
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
+
+#[test]
+fn placeholders_are_the_default_and_are_not_unbundled() {
+    let root = project("binary-placeholder");
+    fbundle(&root, &["-s", "src", "-f", "---", "-g", "*", "--no-cache"]);
+    let bundle = fs::read_to_string(root.join("file_bundle.txt")).unwrap();
+    assert!(bundle.contains("--- blob.bin (binary)\n[binary file: 768 bytes, sha256 "), "{}", bundle);
+
+    let output = fbundle(&root, &["unbundle", "-f", "---", "file_bundle.txt", "-o", "out"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("blob.bin is a placeholder for a binary file. Skipping."));
+    assert!(!root.join("out/blob.bin").exists());
+    assert!(root.join("out/a.txt").exists());
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn skipped_binary_files_are_left_out() {
+    let root = project("binary-skip");
+    let output = fbundle(&root, &["-s", "src", "-o", "-", "-f", "---", "-g", "*", "--no-cache", "--binary", "skip"]);
+    let bundle = String::from_utf8(output.stdout).unwrap();
+    assert!(!bundle.contains("blob.bin"), "{}", bundle);
+    assert!(bundle.contains("--- a.txt\ntext\n"));
+
+    fs::remove_dir_all(&root).unwrap();
+}
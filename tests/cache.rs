@@ -0,0 +1,55 @@
+use std::fs;
+
+mod common;
+use common::{fbundle_cached, scratch_dir};
+
+#[test]
+fn clean_removes_only_what_the_cache_wrote() {
+    let root = scratch_dir("cache-clean");
+    let cache = root.join("shared");
+    fs::create_dir_all(root.join("src")).unwrap();
+    fs::create_dir_all(&cache).unwrap();
+    fs::write(root.join("src/a.rs"), "a\n").unwrap();
+    fs::write(cache.join("notes.txt"), "mine").unwrap();
+
+    fbundle_cached(&root, &cache, &["-s", "src", "-f", "---", "-g", "*"]);
+    assert!(fs::read_dir(&cache).unwrap().count() > 1);
+    fbundle_cached(&root, &cache, &["cache", "clean"]);
+    let left: Vec<_> = fs::read_dir(&cache).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+    assert_eq!(left, ["notes.txt"]);
+
+    // A directory holding nothing else goes as well.
+    fs::remove_file(cache.join("notes.txt")).unwrap();
+    fbundle_cached(&root, &cache, &["-s", "src", "-f", "---", "-g", "*"]);
+    fbundle_cached(&root, &cache, &["cache", "clean"]);
+    assert!(!cache.exists());
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn only_transcoded_bodies_are_copied_into_the_cache() {
+    let root = scratch_dir("cache-bodies");
+    let cache = root.join("cache");
+    fs::create_dir_all(root.join("src")).unwrap();
+    fs::write(root.join("src/utf8.txt"), "caf\u{e9}\n").unwrap();
+    fs::write(root.join("src/latin1.txt"), b"caf\xe9\n").unwrap();
+
+    let args = ["-s", "src", "-f", "---", "-g", "*", "-v"];
+    let first = fbundle_cached(&root, &cache, &args);
+    let bundle = fs::read(root.join("file_bundle.txt")).unwrap();
+    let bodies: Vec<_> = fs::read_dir(&cache)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "body"))
+        .collect();
+    assert_eq!(bodies.len(), 1);
+    assert_eq!(fs::read_to_string(&bodies[0]).unwrap(), "caf\u{e9}\n");
+    assert!(String::from_utf8_lossy(&first.stdout).contains("Reused 0 of 2 files"));
+
+    let second = fbundle_cached(&root, &cache, &args);
+    assert!(String::from_utf8_lossy(&second.stdout).contains("Reused 2 of 2 files"));
+    assert_eq!(fs::read(root.join("file_bundle.txt")).unwrap(), bundle);
+
+    fs::remove_dir_all(&root).unwrap();
+}
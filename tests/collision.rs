@@ -0,0 +1,68 @@
+use std::fs;
+
+mod common;
+use common::{fbundle, scratch_dir};
+
+const NOTES: &str = "title\n--- not a file\n\\--- escaped already\n---- longer\n";
+
+#[test]
+fn colliding_lines_are_escaped_and_restored() {
+    let root = scratch_dir("collision-escape");
+    fs::create_dir_all(root.join("src")).unwrap();
+    fs::write(root.join("src/notes.md"), NOTES).unwrap();
+
+    fbundle(&root, &["-s", "src", "-f", "---", "-g", "*", "--no-cache", "--sep-collision", "escape"]);
+    let bundle = fs::read_to_string(root.join("file_bundle.txt")).unwrap();
+    assert!(bundle.contains("\n\\--- not a file\n\\\\--- escaped already\n---- longer\n"));
+
+    fbundle(&root, &["unbundle", "-f", "---", "file_bundle.txt", "-o", "out", "--unescape"]);
+    assert_eq!(fs::read_to_string(root.join("out/notes.md")).unwrap(), NOTES);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn sep_auto_picks_a_separator_no_line_collides_with() {
+    let root = scratch_dir("collision-auto");
+    fs::create_dir_all(root.join("src")).unwrap();
+    fs::write(root.join("src/notes.md"), NOTES).unwrap();
+
+    let output = fbundle(&root, &["-s", "src", "-o", "-", "-f", "---", "-g", "*", "--no-cache", "--sep-auto"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("File separator: -----"));
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with(&format!("----- notes.md\n{}", NOTES)));
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn multi_line_separators_with_placeholders_round_trip() {
+    let root = scratch_dir("collision-placeholders");
+    fs::create_dir_all(root.join("src")).unwrap();
+    fs::write(root.join("src/a.rs"), "fn main() {}\n\n<<<1/2: fake.rs>>>\n").unwrap();
+    fs::write(root.join("src/b.txt"), "tab\there\n").unwrap();
+    let sep = "\\n<<<{index}/{count}: {path}>>>";
+
+    let args = ["-s", "src", "-f", sep, "--header-template", "{sep}", "-g", "*", "--no-cache", "--sep-collision", "escape"];
+    fbundle(&root, &args);
+    let bundle = fs::read_to_string(root.join("file_bundle.txt")).unwrap();
+    assert!(bundle.starts_with("\n<<<1/2: a.rs>>>\nfn main() {}\n\\\n<<<1/2: fake.rs>>>\n\n\n<<<2/2: b.txt>>>\n"));
+
+    fbundle(&root, &["unbundle", "-f", sep, "--header-template", "{sep}", "file_bundle.txt", "-o", "out", "--unescape"]);
+    assert_eq!(fs::read_to_string(root.join("out/a.rs")).unwrap(), "fn main() {}\n\n<<<1/2: fake.rs>>>\n");
+    assert_eq!(fs::read_to_string(root.join("out/b.txt")).unwrap(), "tab\there\n");
+    assert!(!root.join("out/fake.rs").exists());
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn sep_auto_skips_only_the_lengths_that_collide() {
+    let root = scratch_dir("collision-auto-gap");
+    fs::create_dir_all(root.join("src")).unwrap();
+    fs::write(root.join("src/rules.md"), "--- a\n------ b\n--------\n").unwrap();
+
+    let output = fbundle(&root, &["-s", "src", "-o", "-", "-f", "---", "-g", "*", "--no-cache", "--sep-auto"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("File separator: ----\n"));
+
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,59 @@
+use std::fs;
+
+mod common;
+use common::{fbundle, run, scratch_dir};
+
+const CONFIG: &str = r#"
+file_sep = "==="
+src_globs = ["*.rs"]
+out_dir = "dist"
+
+[profile.docs]
+src_globs = ["*.md"]
+bundle_name = "docs"
+"#;
+
+#[test]
+fn the_config_is_found_in_a_parent_and_overridden_by_profiles_and_the_command_line() {
+    let root = scratch_dir("config-layers");
+    let src = root.join("src");
+    fs::create_dir_all(&src).unwrap();
+    fs::create_dir_all(root.join("dist")).unwrap();
+    fs::write(root.join("fbundle.toml"), CONFIG).unwrap();
+    fs::write(src.join("a.rs"), "a\n").unwrap();
+    fs::write(src.join("b.md"), "b\n").unwrap();
+
+    // Found above the source directory, with out_dir relative to the config rather than to src.
+    fbundle(&src, &["--no-cache"]);
+    assert_eq!(fs::read_to_string(root.join("dist/file_bundle.txt")).unwrap(), "=== a.rs\na\n\n");
+    assert!(!src.join("dist").exists());
+
+    // A profile overrides the defaults, and keeps those it does not set.
+    fbundle(&src, &["--no-cache", "--profile", "docs"]);
+    assert_eq!(fs::read_to_string(root.join("dist/docs.txt")).unwrap(), "=== b.md\nb\n\n");
+
+    // The command line overrides both, and a list given there replaces the config's.
+    fbundle(&src, &["--no-cache", "--profile", "docs", "-f", "+++", "-g", "*"]);
+    assert_eq!(fs::read_to_string(root.join("dist/docs.txt")).unwrap(), "+++ a.rs\na\n\n+++ b.md\nb\n\n");
+
+    let missing = run(&src, &["--no-cache", "--profile", "nope"]);
+    assert!(!missing.status.success());
+    assert!(String::from_utf8_lossy(&missing.stderr).contains("no profile 'nope'"));
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn unknown_keys_are_an_error() {
+    let root = scratch_dir("config-unknown");
+    fs::create_dir_all(root.join("src")).unwrap();
+    fs::write(root.join("src/a.rs"), "a\n").unwrap();
+    fs::write(root.join("fbundle.toml"), "file_sep = \"---\"\n\n[profile.x]\ncolour = \"red\"\n").unwrap();
+
+    let output = run(&root, &["-s", "src", "--no-cache"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("fbundle.toml: [profile.x] unknown setting 'colour'"));
+    assert!(!root.join("file_bundle.txt").exists());
+
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,64 @@
+use std::fs;
+use std::path::Path;
+
+mod common;
+use common::{fbundle, fbundle_cached, scratch_dir};
+
+/// Bundles `root/src` into `root` with the cache in `root/cache`, or without
+/// a cache if `cache` is false.
+fn bundle(root: &Path, cache: bool, extra: &[&str]) -> Vec<u8> {
+    let args = [&["-s", "src", "-f", "---", "-g", "*.rs"][..], extra].concat();
+    if cache {
+        fbundle_cached(root, &root.join("cache"), &args);
+    } else {
+        fbundle(root, &[&args[..], &["--no-cache"]].concat());
+    }
+    fs::read(root.join("file_bundle.txt")).unwrap()
+}
+
+#[test]
+fn identical_input_gives_identical_bundle() {
+    let root = scratch_dir("deterministic");
+    let src = root.join("src");
+    for dir in ["a", "b/c", "d"] {
+        fs::create_dir_all(src.join(dir)).unwrap();
+    }
+    for i in 0..64 {
+        let dir = ["a", "b/c", "d"][i % 3];
+        fs::write(src.join(dir).join(format!("f{}.rs", i)), "x".repeat(i * 37)).unwrap();
+    }
+
+    // Files read from the cache are a separate path to the same bundle.
+    for order in ["path", "walk", "size", "mtime"] {
+        let first = bundle(&root, false, &["--sort", order]);
+        let _ = fs::remove_dir_all(root.join("cache"));
+        assert_eq!(first, bundle(&root, true, &["--sort", order]), "--sort {}, cold cache", order);
+        assert!(fs::read_dir(root.join("cache")).unwrap().next().is_some());
+        for _ in 0..4 {
+            assert_eq!(first, bundle(&root, true, &["--sort", order]), "--sort {}, warm cache", order);
+            assert_eq!(first, bundle(&root, false, &["--sort", order]), "--sort {}, no cache", order);
+        }
+    }
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn priority_globs_come_first() {
+    let root = scratch_dir("priority");
+    let src = root.join("src");
+    fs::create_dir_all(&src).unwrap();
+    for name in ["a.rs", "b.rs", "main.rs", "z.rs"] {
+        fs::write(src.join(name), name).unwrap();
+    }
+
+    let output = String::from_utf8(bundle(&root, false, &["--priority", "z.rs", "--priority", "main.rs"])).unwrap();
+    let order: Vec<_> = output
+        .lines()
+        .filter_map(|line| line.strip_prefix("--- "))
+        .map(|path| path.rsplit('/').next().unwrap())
+        .collect();
+    assert_eq!(order, ["z.rs", "main.rs", "a.rs", "b.rs"]);
+
+    fs::remove_dir_all(&root).unwrap();
+}
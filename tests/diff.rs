@@ -0,0 +1,60 @@
+use std::fs;
+use std::path::PathBuf;
+
+mod common;
+use common::{fbundle, scratch_dir};
+
+/// An old and a new version of a source directory: a.rs changed, b.rs did not,
+/// c.rs was removed and n.rs added.
+fn versions(name: &str) -> PathBuf {
+    let root = scratch_dir(name);
+    for dir in ["old", "src"] {
+        fs::create_dir_all(root.join(dir)).unwrap();
+    }
+    fs::write(root.join("old/a.rs"), "1\n2\n3\n4\n5\n6\n7\n8\n9\n").unwrap();
+    fs::write(root.join("src/a.rs"), "1\n2\n3\n4\nfive\n6\n7\n8\n9\n").unwrap();
+    fs::write(root.join("old/b.rs"), "same\n").unwrap();
+    fs::write(root.join("src/b.rs"), "same\n").unwrap();
+    fs::write(root.join("old/c.rs"), "gone\n").unwrap();
+    fs::write(root.join("src/n.rs"), "new\n").unwrap();
+    root
+}
+
+const ARGS: &[&str] = &["-s", "src", "-f", "---", "-g", "*.rs", "--no-cache"];
+
+#[test]
+fn diff_against_a_directory() {
+    let root = versions("diff-directory");
+    fbundle(&root, &[ARGS, &["--diff-against", "old", "--diff-context", "1"]].concat());
+    let bundle = fs::read_to_string(root.join("file_bundle.txt")).unwrap();
+    assert_eq!(bundle, "--- a.rs (diff)\n@@ -4,3 +4,3 @@\n 4\n-5\n+five\n 6\n\n--- n.rs\nnew\n\n");
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn diff_against_an_earlier_bundle() {
+    let root = versions("diff-bundle");
+    fs::create_dir_all(root.join("base")).unwrap();
+    fbundle(&root, &["-s", "old", "-o", "base", "-f", "---", "-g", "*.rs", "--no-cache"]);
+    fbundle(&root, &[ARGS, &["--diff-against", "base/file_bundle.txt"]].concat());
+    let bundle = fs::read_to_string(root.join("file_bundle.txt")).unwrap();
+    assert_eq!(bundle, "--- a.rs (diff)\n@@ -2,7 +2,7 @@\n 2\n 3\n 4\n-5\n+five\n 6\n 7\n 8\n\n--- n.rs\nnew\n\n");
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn unchanged_files_are_left_out() {
+    let root = versions("diff-unchanged");
+    let output = fbundle(&root, &[ARGS, &["--diff-against", "old", "-v"]].concat());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Leaving out src/b.rs: unchanged since old"));
+    let bundle = fs::read_to_string(root.join("file_bundle.txt")).unwrap();
+    assert!(!bundle.contains("b.rs"), "{}", bundle);
+
+    // With nothing changed, the bundle is empty.
+    fbundle(&root, &["-s", "src", "-f", "---", "-g", "b.rs", "--no-cache", "--diff-against", "old"]);
+    assert_eq!(fs::read_to_string(root.join("file_bundle.txt")).unwrap(), "");
+
+    fs::remove_dir_all(&root).unwrap();
+}
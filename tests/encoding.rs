@@ -0,0 +1,74 @@
+use std::fs;
+
+mod common;
+use common::{fbundle, scratch_dir};
+
+const TEXT: &str = "gr\u{fc}\u{df}e \u{2713}\r\nzwei\n";
+const LEGACY: &[u8] = b"/* caf\xe9 \x96 na\xefve */\nint x;\n";
+
+fn utf16(text: &str, big_endian: bool) -> Vec<u8> {
+    let units = std::iter::once(0xfeff).chain(text.encode_utf16());
+    units.flat_map(|unit| if big_endian { unit.to_be_bytes() } else { unit.to_le_bytes() }).collect()
+}
+
+#[test]
+fn utf16_and_legacy_files_are_transcoded_and_restored() {
+    let root = scratch_dir("encoding-round-trip");
+    fs::create_dir_all(root.join("src")).unwrap();
+    fs::write(root.join("src/le.txt"), utf16(TEXT, false)).unwrap();
+    fs::write(root.join("src/be.txt"), utf16(TEXT, true)).unwrap();
+    fs::write(root.join("src/legacy.c"), LEGACY).unwrap();
+
+    fbundle(&root, &["-s", "src", "-f", "---", "-g", "*", "--no-cache"]);
+    let bundle = fs::read_to_string(root.join("file_bundle.txt")).unwrap();
+    assert!(bundle.contains(&format!("--- be.txt (UTF-16BE)\n{}\n", TEXT)), "{}", bundle);
+    assert!(bundle.contains(&format!("--- le.txt (UTF-16LE)\n{}\n", TEXT)), "{}", bundle);
+    assert!(bundle.contains("--- legacy.c (windows-1252)\n/* caf\u{e9} \u{2013} na\u{ef}ve */\n"), "{}", bundle);
+
+    fbundle(&root, &["unbundle", "-f", "---", "file_bundle.txt", "-o", "out"]);
+    assert_eq!(fs::read(root.join("out/le.txt")).unwrap(), utf16(TEXT, false));
+    assert_eq!(fs::read(root.join("out/be.txt")).unwrap(), utf16(TEXT, true));
+    assert_eq!(fs::read(root.join("out/legacy.c")).unwrap(), LEGACY);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn undecodable_text_is_binary_unless_lossy() {
+    let root = scratch_dir("encoding-lossy");
+    fs::create_dir_all(root.join("src")).unwrap();
+    fs::write(root.join("src/bad.txt"), b"ok \xff x\n").unwrap();
+
+    let args = ["-s", "src", "-o", "-", "-f", "---", "-g", "*", "--no-cache", "--input-encoding", "utf-8"];
+    let strict = fbundle(&root, &args);
+    assert!(String::from_utf8(strict.stdout).unwrap().starts_with("--- bad.txt (binary)\n"));
+
+    let lossy = fbundle(&root, &[&args[..], &["--lossy"]].concat());
+    assert_eq!(String::from_utf8(lossy.stdout).unwrap(), "--- bad.txt\nok \u{fffd} x\n\n");
+    assert!(String::from_utf8_lossy(&lossy.stderr).contains("is not valid UTF-8. Replacing undecodable bytes."));
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn input_encoding_only_applies_to_text_that_is_not_utf8() {
+    let root = scratch_dir("encoding-forced");
+    fs::create_dir_all(root.join("src")).unwrap();
+    fs::write(root.join("src/utf8.txt"), "caf\u{e9}\n").unwrap();
+    fs::write(root.join("src/legacy.txt"), b"caf\xe9\n").unwrap();
+
+    let args = ["-s", "src", "-o", "-", "-f", "---", "-g", "*", "--no-cache", "--input-encoding", "windows-1252"];
+    let output = fbundle(&root, &args);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "--- legacy.txt (windows-1252)\ncaf\u{e9}\n\n--- utf8.txt\ncaf\u{e9}\n\n"
+    );
+
+    // Without a byte order mark, UTF-16 text can be valid UTF-8 too; a forced UTF-16 wins.
+    fs::write(root.join("src/wide.txt"), b"h\0i\0\n\0").unwrap();
+    let args = ["-s", "src", "-o", "-", "-f", "---", "-g", "wide.txt", "--no-cache", "--input-encoding", "utf-16le"];
+    let output = fbundle(&root, &args);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "--- wide.txt (UTF-16LE)\nhi\n\n");
+
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,30 @@
+use std::fs;
+
+mod common;
+use common::{fbundle_stdin, scratch_dir};
+
+fn headers(bundle: &str) -> Vec<&str> {
+    bundle.lines().filter_map(|line| line.strip_prefix("--- ")).collect()
+}
+
+#[test]
+fn a_list_on_stdin_is_split_at_nul_bytes_if_it_has_any() {
+    let root = scratch_dir("files-from-stdin");
+    fs::create_dir_all(root.join("src")).unwrap();
+    for name in ["a.rs", "b.rs", "two\nlines.rs", "notes.md"] {
+        fs::write(root.join("src").join(name), "x\n").unwrap();
+    }
+    let args = ["-s", "src", "-f", "---", "-g", "*.rs", "--no-cache", "--sort", "walk", "--files-from", "-"];
+
+    // A NUL-separated list, as from 'git ls-files -z', can name a file with a newline.
+    fbundle_stdin(&root, &args, b"src/b.rs\0src/two\nlines.rs\0src/notes.md\0src/a.rs\0");
+    let bundle = fs::read_to_string(root.join("file_bundle.txt")).unwrap();
+    assert_eq!(bundle, "--- b.rs\nx\n\n--- two\nlines.rs\nx\n\n--- a.rs\nx\n\n");
+
+    // Without one, it is split into lines, and CRLF line ends are accepted.
+    fbundle_stdin(&root, &args, b"src/b.rs\r\nsrc/notes.md\r\nsrc/a.rs\r\n");
+    let bundle = fs::read_to_string(root.join("file_bundle.txt")).unwrap();
+    assert_eq!(headers(&bundle), ["b.rs", "a.rs"]);
+
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,81 @@
+use std::fs;
+
+use std::path::{Path, PathBuf};
+
+mod common;
+use common::{fbundle, scratch_dir};
+
+const QUOTED: &str = "say \"hi\"\\\n\ttab\u{1}\n";
+const FENCED: &str = "a ```\n````rust\n</b> & ]]>\n";
+
+/// A source directory with files that need escaping in every format.
+fn project(name: &str) -> PathBuf {
+    let root = scratch_dir(name);
+    fs::create_dir_all(root.join("src")).unwrap();
+    fs::write(root.join("src/q\"uote.txt"), QUOTED).unwrap();
+    fs::write(root.join("src/fence.md"), FENCED).unwrap();
+    root
+}
+
+fn bundle(root: &Path, format: &str) -> String {
+    let output = fbundle(root, &["-s", "src", "-o", "-", "-g", "*", "--no-cache", "--format", format]);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn json_and_jsonl_contents_parse_back_to_the_files() {
+    let root = project("formats-json");
+    let files: Vec<serde_json::Value> = serde_json::from_str(&bundle(&root, "json")).unwrap();
+    let lines: Vec<serde_json::Value> = bundle(&root, "jsonl").lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    for files in [files, lines] {
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0]["path"], "fence.md");
+        assert_eq!(files[0]["contents"], FENCED);
+        assert_eq!(files[1]["path"], "q\"uote.txt");
+        assert_eq!(files[1]["contents"], QUOTED);
+    }
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn xml_escapes_attributes_and_splits_cdata_sections() {
+    let root = project("formats-xml");
+    assert_eq!(
+        bundle(&root, "xml"),
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<files>\n\
+         \x20 <file path=\"fence.md\" size=\"26\" encoding=\"utf-8\">\
+         <![CDATA[a ```\n````rust\n</b> & ]]]]><![CDATA[>\n]]></file>\n\
+         \x20 <file path=\"q&quot;uote.txt\" size=\"16\" encoding=\"utf-8\">\
+         <![CDATA[say \"hi\"\\\n\ttab\u{fffd}\n]]></file>\n\
+         </files>\n"
+    );
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn markdown_fences_are_longer_than_any_backtick_run() {
+    let root = project("formats-markdown");
+    let markdown = bundle(&root, "markdown");
+    assert!(markdown.starts_with(&format!("## fence.md\n\n`````markdown\n{}`````\n\n", FENCED)), "{}", markdown);
+    assert!(markdown.contains(&format!("## q\"uote.txt\n\n```text\n{}```\n", QUOTED)), "{}", markdown);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn xml_output_holds_no_characters_xml_forbids() {
+    let root = scratch_dir("formats-xml-control");
+    fs::create_dir_all(root.join("src")).unwrap();
+    fs::write(root.join("src/page\tbreak.txt"), "one\x0ctwo\r\n]]>\n").unwrap();
+
+    let output = fbundle(&root, &["-s", "src", "-o", "-", "-g", "*", "--no-cache", "--format", "xml"]);
+    let xml = String::from_utf8(output.stdout).unwrap();
+    assert!(xml.contains(
+        "<file path=\"page&#9;break.txt\" size=\"13\" encoding=\"utf-8\">\
+         <![CDATA[one\u{fffd}two]]>&#13;<![CDATA[\n]]]]><![CDATA[>\n]]></file>"
+    ));
+
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,206 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+mod common;
+use common::{fbundle, run, scratch_dir};
+
+fn git(repo: &Path, args: &[&str]) {
+    let status = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(["-c", "user.name=fbundle", "-c", "user.email=fbundle@example.com", "-c", "commit.gpgsign=false"])
+        .args(args)
+        .status()
+        .unwrap();
+    assert!(status.success(), "git {:?}", args);
+}
+
+fn write(repo: &Path, path: &str, contents: &str) {
+    let path = repo.join(path);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, contents).unwrap();
+}
+
+/// A repository with a first commit of a.rs, b.rs and docs/c.md, and a second
+/// commit that changes b.rs and adds d.rs.
+fn repo(name: &str) -> PathBuf {
+    let repo = scratch_dir(&format!("git-{}", name));
+    git(&repo, &["init", "-q"]);
+    write(&repo, "a.rs", "a\n");
+    write(&repo, "b.rs", "b\n");
+    write(&repo, "docs/c.md", "c\n");
+    write(&repo, ".gitignore", "*.log\n");
+    git(&repo, &["add", "."]);
+    git(&repo, &["commit", "-q", "-m", "first"]);
+    write(&repo, "b.rs", "b, changed\n");
+    write(&repo, "d.rs", "d\n");
+    git(&repo, &["add", "."]);
+    git(&repo, &["commit", "-q", "-m", "second"]);
+    repo
+}
+
+/// Bundles `src` into `out` and returns the paths in the bundle.
+fn bundled(src: &Path, out: &Path, extra: &[&str]) -> Vec<String> {
+    let out_dir = out.to_str().unwrap();
+    fbundle(src, &[&["-s", ".", "-o", out_dir, "-f", "---", "--no-cache"][..], extra].concat());
+    fs::read_to_string(out.join("file_bundle.txt"))
+        .unwrap()
+        .lines()
+        .filter_map(|line| line.strip_prefix("--- "))
+        .map(str::to_string)
+        .collect()
+}
+
+#[test]
+fn tracked_files_only() {
+    let repo = repo("tracked");
+    let out = scratch_dir("git-tracked-out");
+    write(&repo, "untracked.rs", "u\n");
+
+    assert_eq!(bundled(&repo, &out, &["-g", "*.rs", "--git-tracked"]), ["a.rs", "b.rs", "d.rs"]);
+    assert_eq!(bundled(&repo, &out, &["-g", "*", "--git-tracked"]), [".gitignore", "a.rs", "b.rs", "d.rs", "docs/c.md"]);
+
+    fs::remove_dir_all(&repo).unwrap();
+    fs::remove_dir_all(&out).unwrap();
+}
+
+#[test]
+fn changed_files_include_untracked_but_not_ignored_or_deleted() {
+    let repo = repo("changed");
+    let out = scratch_dir("git-changed-out");
+    write(&repo, "a.rs", "a, edited\n");
+    write(&repo, "docs/c.md", "c, staged\n");
+    git(&repo, &["add", "docs/c.md"]);
+    write(&repo, "new.rs", "n\n");
+    write(&repo, "debug.log", "ignored\n");
+    fs::remove_file(repo.join("d.rs")).unwrap();
+
+    assert_eq!(bundled(&repo, &out, &["-g", "*", "--git-changed"]), ["a.rs", "docs/c.md", "new.rs"]);
+    // The globs still apply, and so does intersecting with the tracked files.
+    assert_eq!(bundled(&repo, &out, &["-g", "*.rs", "--git-changed"]), ["a.rs", "new.rs"]);
+    assert_eq!(bundled(&repo, &out, &["-g", "*", "--git-changed", "--git-tracked"]), ["a.rs", "docs/c.md"]);
+
+    fs::remove_dir_all(&repo).unwrap();
+    fs::remove_dir_all(&out).unwrap();
+}
+
+#[test]
+fn diff_range_selects_touched_files() {
+    let repo = repo("diff");
+    let out = scratch_dir("git-diff-out");
+
+    assert_eq!(bundled(&repo, &out, &["-g", "*", "--git-diff", "HEAD~1..HEAD"]), ["b.rs", "d.rs"]);
+    assert_eq!(bundled(&repo, &out, &["-g", "d.*", "--git-diff", "HEAD~1..HEAD"]), ["d.rs"]);
+    // A single revision compares it with the working tree.
+    write(&repo, "a.rs", "a, edited\n");
+    assert_eq!(bundled(&repo, &out, &["-g", "*", "--git-diff", "HEAD~1"]), ["a.rs", "b.rs", "d.rs"]);
+
+    fs::remove_dir_all(&repo).unwrap();
+    fs::remove_dir_all(&out).unwrap();
+}
+
+#[test]
+fn paths_are_relative_to_a_source_directory_inside_the_repository() {
+    let repo = repo("subdir");
+    let out = scratch_dir("git-subdir-out");
+    write(&repo, "docs/c.md", "c, edited\n");
+    write(&repo, "docs/new.md", "n\n");
+    write(&repo, "a.rs", "a, edited\n");
+
+    assert_eq!(bundled(&repo.join("docs"), &out, &["-g", "*", "--git-changed"]), ["c.md", "new.md"]);
+    assert_eq!(bundled(&repo.join("docs"), &out, &["-g", "*", "--git-tracked"]), ["c.md"]);
+
+    fs::remove_dir_all(&repo).unwrap();
+    fs::remove_dir_all(&out).unwrap();
+}
+
+#[test]
+fn outside_a_repository_is_an_error() {
+    let dir = scratch_dir("git-no-repo");
+    write(&dir, "a.rs", "a\n");
+    let output = Command::new(env!("CARGO_BIN_EXE_fbundle"))
+        .arg("-s")
+        .arg(&dir)
+        .arg("-o")
+        .arg(&dir)
+        .args(["-f", "---", "-g", "*", "--git-tracked"])
+        .env("GIT_CEILING_DIRECTORIES", std::env::temp_dir())
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("git ls-files"));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn revision_contents_are_read_without_checking_it_out() {
+    let repo = repo("rev");
+    let out = scratch_dir("git-rev-out");
+    write(&repo, "a.rs", "a, uncommitted\n");
+
+    assert_eq!(bundled(&repo, &out, &["-g", "*.rs", "--git-rev", "HEAD~1"]), ["HEAD~1:a.rs", "HEAD~1:b.rs"]);
+    let bundle = fs::read_to_string(out.join("file_bundle.txt")).unwrap();
+    assert!(bundle.contains("--- HEAD~1:a.rs\na\n"));
+    assert!(bundle.contains("--- HEAD~1:b.rs\nb\n"));
+    // Other git selections narrow the revision down, and the working tree is untouched.
+    assert_eq!(bundled(&repo, &out, &["-g", "*", "--git-rev", "HEAD", "--git-diff", "HEAD~1..HEAD"]), ["HEAD:b.rs", "HEAD:d.rs"]);
+    assert_eq!(fs::read_to_string(repo.join("a.rs")).unwrap(), "a, uncommitted\n");
+
+    fs::remove_dir_all(&repo).unwrap();
+    fs::remove_dir_all(&out).unwrap();
+}
+
+#[test]
+fn revision_bundles_are_read_back_without_the_revision() {
+    let repo = repo("rev-read-back");
+    let out = scratch_dir("git-rev-read-back-out");
+    let out_dir = out.to_str().unwrap();
+
+    bundled(&repo, &out, &["-g", "*.rs", "--git-rev", "HEAD~1", "--manifest", "embedded"]);
+    // The manifest records the revision, so that its label is taken off the paths.
+    fbundle(&out, &["unbundle", "file_bundle.txt", "-o", "restored"]);
+    assert_eq!(fs::read_to_string(out.join("restored/a.rs")).unwrap(), "a\n");
+    assert_eq!(fs::read_to_string(out.join("restored/b.rs")).unwrap(), "b\n");
+    let applied = fbundle(&repo, &["apply", "--dry-run", &format!("{}/file_bundle.txt", out_dir), "-s", "."]);
+    let stdout = String::from_utf8_lossy(&applied.stdout);
+    assert!(stdout.contains("--- a/b.rs\n+++ b/b.rs\n"), "{}", stdout);
+    assert!(!stdout.contains("HEAD~1:"), "{}", stdout);
+    assert!(String::from_utf8_lossy(&applied.stderr).contains("Conflict: b.rs changed since the bundle was created"));
+
+    // Without a manifest, --git-rev says which revision to take off.
+    bundled(&repo, &out, &["-g", "*.rs", "--git-rev", "HEAD~1"]);
+    fbundle(&out, &["unbundle", "file_bundle.txt", "-o", "plain", "--git-rev", "HEAD~1"]);
+    assert_eq!(fs::read_to_string(out.join("plain/b.rs")).unwrap(), "b\n");
+    let output = run(&out, &["unbundle", "file_bundle.txt", "-o", "wrong", "--git-rev", "HEAD"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("HEAD~1:a.rs: path is not labelled with the revision HEAD"));
+
+    fs::remove_dir_all(&repo).unwrap();
+    fs::remove_dir_all(&out).unwrap();
+}
+
+#[test]
+fn revision_paths_are_relative_to_a_source_directory_inside_the_repository() {
+    let repo = repo("rev-subdir");
+    let out = scratch_dir("git-rev-subdir-out");
+
+    assert_eq!(bundled(&repo.join("docs"), &out, &["-g", "*", "--git-rev", "HEAD~1"]), ["HEAD~1:c.md"]);
+
+    fs::remove_dir_all(&repo).unwrap();
+    fs::remove_dir_all(&out).unwrap();
+}
+
+#[test]
+fn diff_against_a_revision_shows_changed_hunks_and_new_files_whole() {
+    let repo = repo("diff-against");
+    let out = scratch_dir("git-diff-against-out");
+
+    assert_eq!(bundled(&repo, &out, &["-g", "*.rs", "--diff-against", "HEAD~1"]), ["b.rs (diff)", "d.rs"]);
+    let bundle = fs::read_to_string(out.join("file_bundle.txt")).unwrap();
+    assert!(bundle.contains("--- b.rs (diff)\n@@ -1 +1 @@\n-b\n+b, changed\n"));
+    assert!(bundle.contains("--- d.rs\nd\n"));
+
+    fs::remove_dir_all(&repo).unwrap();
+    fs::remove_dir_all(&out).unwrap();
+}
@@ -0,0 +1,52 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+mod common;
+use common::{fbundle, scratch_dir};
+
+/// A source directory with ignore files of every kind, each leaving out one file.
+fn project(name: &str) -> PathBuf {
+    let root = scratch_dir(name);
+    let src = root.join("src");
+    fs::create_dir_all(src.join("sub/deep")).unwrap();
+    fs::create_dir_all(src.join(".git/info")).unwrap();
+    fs::write(src.join(".gitignore"), "*.log\n").unwrap();
+    fs::write(src.join("sub/.gitignore"), "secret.txt\n").unwrap();
+    fs::write(src.join(".git/info/exclude"), "local.txt\n").unwrap();
+    fs::write(src.join("sub/.ignore"), "gen.rs\n").unwrap();
+    fs::write(src.join(".fbundleignore"), "draft.md\n").unwrap();
+    for file in ["a.rs", "app.log", "local.txt", "draft.md", "sub/b.rs", "sub/secret.txt", "sub/gen.rs", "sub/deep/draft.md"] {
+        fs::write(src.join(file), "x\n").unwrap();
+    }
+    root
+}
+
+/// The paths bundled with the ignore options in `extra`.
+fn bundled(root: &Path, extra: &[&str]) -> Vec<String> {
+    let globs = ["-g", "*.rs", "-g", "*.log", "-g", "*.txt", "-g", "*.md"];
+    let output = fbundle(root, &[&["-s", "src", "-o", "-", "-f", "---", "--no-cache"][..], &globs, extra].concat());
+    String::from_utf8(output.stdout).unwrap().lines().filter_map(|line| line.strip_prefix("--- ")).map(str::to_string).collect()
+}
+
+#[test]
+fn ignore_files_are_only_read_when_asked_for() {
+    let root = project("ignore-kinds");
+    let all = ["a.rs", "app.log", "draft.md", "local.txt", "sub/b.rs", "sub/deep/draft.md", "sub/gen.rs", "sub/secret.txt"];
+    assert_eq!(bundled(&root, &[]), all);
+    // Nested .gitignore files apply to their own directory, and .git/info/exclude to the repository.
+    assert_eq!(
+        bundled(&root, &["--respect-gitignore"]),
+        ["a.rs", "draft.md", "sub/b.rs", "sub/deep/draft.md", "sub/gen.rs"]
+    );
+    assert_eq!(
+        bundled(&root, &["--respect-ignore-files"]),
+        ["a.rs", "app.log", "draft.md", "local.txt", "sub/b.rs", "sub/deep/draft.md", "sub/secret.txt"]
+    );
+    // A custom ignore file applies to the subdirectories below it too.
+    assert_eq!(
+        bundled(&root, &["--custom-ignore-file", ".fbundleignore"]),
+        ["a.rs", "app.log", "local.txt", "sub/b.rs", "sub/gen.rs", "sub/secret.txt"]
+    );
+
+    fs::remove_dir_all(&root).unwrap();
+}
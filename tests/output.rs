@@ -0,0 +1,33 @@
+use std::fs;
+
+mod common;
+use common::{fbundle, scratch_dir};
+
+fn headers(bundle: &str) -> Vec<&str> {
+    bundle.lines().filter_map(|line| line.strip_prefix("--- ")).collect()
+}
+
+#[test]
+fn the_files_of_the_bundle_are_never_bundled() {
+    let root = scratch_dir("output-inside-source");
+    for name in ["a", "b", "c"] {
+        fs::write(root.join(format!("{}.txt", name)), name.repeat(300) + "\n").unwrap();
+    }
+    let args = ["-s", ".", "-o", ".", "-f", "---", "--no-cache", "--manifest", "sidecar"];
+
+    // The parts and index of a split bundle match *.txt, so does the bundle itself.
+    fbundle(&root, &[&args[..], &["-g", "*.txt", "--max-bytes", "400"]].concat());
+    assert!(root.join("file_bundle.index.txt").exists());
+    for _ in 0..2 {
+        fbundle(&root, &[&args[..], &["-g", "*.txt"]].concat());
+        let bundle = fs::read_to_string(root.join("file_bundle.txt")).unwrap();
+        assert_eq!(headers(&bundle), ["a.txt", "b.txt", "c.txt"]);
+    }
+
+    // The sidecar manifest is left out as well.
+    fbundle(&root, &[&args[..], &["-g", "*"]].concat());
+    let bundle = fs::read_to_string(root.join("file_bundle.txt")).unwrap();
+    assert_eq!(headers(&bundle), ["a.txt", "b.txt", "c.txt"]);
+
+    fs::remove_dir_all(&root).unwrap();
+}
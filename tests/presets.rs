@@ -0,0 +1,61 @@
+use std::fs;
+use std::path::PathBuf;
+
+mod common;
+use common::{fbundle, scratch_dir};
+
+/// A scratch directory with a src/ of two small files.
+fn project(name: &str) -> PathBuf {
+    let dir = scratch_dir(&format!("presets-{}", name));
+    fs::create_dir_all(dir.join("src")).unwrap();
+    fs::write(dir.join("src/main.rs"), "fn main() {}\n").unwrap();
+    fs::write(dir.join("src/notes.txt"), "tab\there\n").unwrap();
+    dir
+}
+
+#[test]
+fn the_separator_defaults_by_format_and_extension() {
+    let root = project("defaults");
+
+    let plain = fbundle(&root, &["-s", "src", "-o", "-", "-g", "*.rs", "--no-cache"]);
+    assert_eq!(String::from_utf8_lossy(&plain.stdout), "--- main.rs\nfn main() {}\n\n");
+    let markdown = fbundle(&root, &["-s", "src", "-o", "-", "-g", "*.rs", "--no-cache", "-e", ".md"]);
+    assert!(String::from_utf8_lossy(&markdown.stdout).starts_with("## main.rs\n"));
+
+    // unbundle picks the same defaults from the extension of the bundle.
+    for (format, bundle) in [("plain", "file_bundle.txt"), ("markdown", "file_bundle.md")] {
+        let out = format!("out-{}", format);
+        fbundle(&root, &["-s", "src", "-g", "*", "--no-cache", "--format", format]);
+        fbundle(&root, &["unbundle", bundle, "-o", &out]);
+        assert_eq!(fs::read_to_string(root.join(&out).join("main.rs")).unwrap(), "fn main() {}\n", "{}", format);
+        assert_eq!(fs::read_to_string(root.join(&out).join("notes.txt")).unwrap(), "tab\there\n", "{}", format);
+    }
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn presets_are_listed_and_read_back() {
+    let root = project("round-trip");
+
+    let listed = String::from_utf8_lossy(&fbundle(&root, &["presets"]).stdout).into_owned();
+    let names: Vec<&str> = listed.lines().filter(|line| !line.starts_with(' ')).collect();
+    assert_eq!(names, ["claude-xml", "markdown-fence", "gpt-plain", "rfc822-like"]);
+
+    for preset in ["claude-xml", "markdown-fence", "gpt-plain", "rfc822-like"] {
+        let out = format!("out-{}", preset);
+        let bundle = if preset == "markdown-fence" { "file_bundle.md" } else { "file_bundle.txt" };
+        fbundle(&root, &["-s", "src", "-g", "*", "--no-cache", "--preset", preset]);
+        fbundle(&root, &["unbundle", "--preset", preset, bundle, "-o", &out]);
+        assert_eq!(fs::read_to_string(root.join(&out).join("main.rs")).unwrap(), "fn main() {}\n", "{}", preset);
+        assert_eq!(fs::read_to_string(root.join(&out).join("notes.txt")).unwrap(), "tab\there\n", "{}", preset);
+    }
+    let bundle = fs::read_to_string(root.join("file_bundle.txt")).unwrap();
+    assert!(bundle.starts_with("--fbundle-boundary\nPath: main.rs\nSize: 13\nLines: 1\n\nfn main() {}\n"));
+
+    // Options given on the command line win over the preset.
+    let output = fbundle(&root, &["-s", "src", "-o", "-", "-g", "*.rs", "--no-cache", "--preset", "gpt-plain", "-f", "***"]);
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("*** main.rs ***\n"));
+
+    fs::remove_dir_all(&root).unwrap();
+}
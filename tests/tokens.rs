@@ -0,0 +1,63 @@
+use std::fs;
+use std::path::PathBuf;
+
+mod common;
+use common::{fbundle, run, scratch_dir};
+
+/// Files of 15, 105 and 25 tokens with `--tokenizer chars`, separator lines included.
+fn project(name: &str) -> PathBuf {
+    let root = scratch_dir(name);
+    fs::create_dir_all(root.join("src")).unwrap();
+    for (file, len) in [("a.rs", 40), ("b.rs", 400), ("c.rs", 80)] {
+        fs::write(root.join("src").join(file), "x".repeat(len) + "\n").unwrap();
+    }
+    root
+}
+
+const ARGS: &[&str] = &["-s", "src", "-f", "---", "-g", "*", "--no-cache", "--tokenizer", "chars"];
+
+#[test]
+fn count_tokens_reports_every_file_and_the_total() {
+    let root = project("tokens-count");
+    let output = fbundle(&root, &[ARGS, &["--count-tokens"]].concat());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Tokens (chars):\n        15  a.rs\n       105  b.rs\n        25  c.rs\n       145  total\n"),
+        "{}",
+        stdout
+    );
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn overflow_fail_writes_nothing() {
+    let root = project("tokens-fail");
+    let output = run(&root, &[ARGS, &["--max-tokens", "100", "--overflow", "fail"]].concat());
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("the bundle has 145 tokens, more than --max-tokens 100; nothing was written"), "{}", stderr);
+    let written: Vec<_> = fs::read_dir(&root).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+    assert_eq!(written, ["src"]);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn overflow_drop_leaves_out_files_from_the_end() {
+    let root = project("tokens-drop");
+    // c.rs is dropped first although b.rs is larger, as it comes last.
+    let output = fbundle(&root, &[ARGS, &["--max-tokens", "140", "--overflow", "drop"]].concat());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Dropping c.rs (25 tokens) to stay within --max-tokens 140"));
+    let bundle = fs::read_to_string(root.join("file_bundle.txt")).unwrap();
+    let paths: Vec<&str> = bundle.lines().filter_map(|line| line.strip_prefix("--- ")).collect();
+    assert_eq!(paths, ["a.rs", "b.rs"]);
+
+    // --priority moves a file to the front, so that it is kept.
+    fbundle(&root, &[ARGS, &["--max-tokens", "100", "--overflow", "drop", "--priority", "c.rs"]].concat());
+    let bundle = fs::read_to_string(root.join("file_bundle.txt")).unwrap();
+    let paths: Vec<&str> = bundle.lines().filter_map(|line| line.strip_prefix("--- ")).collect();
+    assert_eq!(paths, ["c.rs", "a.rs"]);
+
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,76 @@
+use std::fs;
+use std::path::PathBuf;
+
+mod common;
+use common::{fbundle, scratch_dir};
+
+fn project(name: &str) -> PathBuf {
+    let root = scratch_dir(name);
+    for dir in ["src/lib/deep", "src/docs"] {
+        fs::create_dir_all(root.join(dir)).unwrap();
+    }
+    for (file, contents) in [
+        ("src/main.rs", "a\n"),
+        ("src/z.rs", "ab\ncd\n"),
+        ("src/lib/mod.rs", "b\n"),
+        ("src/lib/notes.md", "n\n"),
+        ("src/lib/deep/x.rs", "c\n"),
+        ("src/docs/readme.md", "d\n"),
+    ] {
+        fs::write(root.join(file), contents).unwrap();
+    }
+    root
+}
+
+const ARGS: &[&str] = &["-s", "src", "-f", "---", "-g", "**/*.rs", "--no-cache", "--tree"];
+
+#[test]
+fn tree_lists_the_bundled_files_before_the_first_one() {
+    let root = project("tree-plain");
+    fbundle(&root, &[ARGS, &["--tree-info", "size,lines"]].concat());
+    let bundle = fs::read_to_string(root.join("file_bundle.txt")).unwrap();
+    assert!(
+        bundle.starts_with(
+            "src\n\
+             |-- lib/\n\
+             |   |-- deep/\n\
+             |   |   `-- x.rs  (2 B, 1 lines)\n\
+             |   `-- mod.rs  (2 B, 1 lines)\n\
+             |-- main.rs  (2 B, 1 lines)\n\
+             `-- z.rs  (6 B, 2 lines)\n\
+             \n\
+             --- lib/deep/x.rs\n"
+        ),
+        "{}",
+        bundle
+    );
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn tree_excluded_marks_files_left_out_of_the_bundle() {
+    let root = project("tree-excluded");
+    fbundle(&root, &[ARGS, &["--tree-excluded"]].concat());
+    let bundle = fs::read_to_string(root.join("file_bundle.txt")).unwrap();
+    // docs/ has nothing in the bundle, so it is a single line.
+    assert!(
+        bundle.starts_with(
+            "src\n\
+             |-- docs/  [excluded]\n\
+             |-- lib/\n\
+             |   |-- deep/\n\
+             |   |   `-- x.rs\n\
+             |   |-- mod.rs\n\
+             |   `-- notes.md  [excluded]\n\
+             |-- main.rs\n\
+             `-- z.rs\n\
+             \n\
+             --- lib/deep/x.rs\n"
+        ),
+        "{}",
+        bundle
+    );
+
+    fs::remove_dir_all(&root).unwrap();
+}
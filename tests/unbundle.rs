@@ -0,0 +1,105 @@
+use std::fs;
+
+mod common;
+use common::{fbundle, run, scratch_dir};
+
+#[test]
+fn paths_that_leave_the_output_directory_are_refused() {
+    let root = scratch_dir("unbundle-escape");
+    for path in ["../evil", "sub/../../evil", "/tmp/evil"] {
+        fs::write(root.join("bundle.txt"), format!("--- good.txt\nfine\n\n--- {}\nboom\n", path)).unwrap();
+        let output = run(&root, &["unbundle", "-f", "---", "bundle.txt", "-o", "out"]);
+        assert!(!output.status.success(), "{} was written", path);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(&format!("refusing to write {}: path escapes the output directory", path)), "{}", stderr);
+        // Every path is checked before anything is written.
+        assert!(!root.join("out").exists());
+    }
+    assert!(!root.join("evil").exists());
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinks_in_the_output_directory_are_not_followed() {
+    let root = scratch_dir("unbundle-symlink");
+    fs::create_dir_all(root.join("out")).unwrap();
+    fs::create_dir_all(root.join("elsewhere")).unwrap();
+    fs::write(root.join("elsewhere/victim.txt"), "untouched\n").unwrap();
+    std::os::unix::fs::symlink(root.join("elsewhere"), root.join("out/link")).unwrap();
+    std::os::unix::fs::symlink(root.join("elsewhere/victim.txt"), root.join("out/victim.txt")).unwrap();
+
+    for (path, reason) in [("link/a/b/evil", "path escapes out"), ("victim.txt", "is a symbolic link")] {
+        fs::write(root.join("bundle.txt"), format!("--- first.txt\nfine\n\n--- {}\nboom\n", path)).unwrap();
+        let output = run(&root, &["unbundle", "-f", "---", "bundle.txt", "-o", "out"]);
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(&format!("refusing to write {}: ", path)) && stderr.contains(reason), "{}", stderr);
+        // Nothing is written or created, not even for the entries before.
+        assert!(!root.join("out/first.txt").exists());
+    }
+    assert!(!root.join("elsewhere/a").exists());
+    assert_eq!(fs::read_to_string(root.join("elsewhere/victim.txt")).unwrap(), "untouched\n");
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn split_parts_unbundle_to_the_original_bytes() {
+    let root = scratch_dir("unbundle-split");
+    fs::create_dir_all(root.join("src")).unwrap();
+    let big: String = (0..200).map(|i| format!("line {:03} of the big file\n", i)).collect();
+    fs::write(root.join("src/big.txt"), &big).unwrap();
+    fs::write(root.join("src/small.txt"), "small\n").unwrap();
+    fs::write(root.join("src/last.txt"), "no newline").unwrap();
+
+    fbundle(&root, &["-s", "src", "-f", "---", "-g", "*", "--no-cache", "--max-bytes", "1500"]);
+    let index = fs::read_to_string(root.join("file_bundle.index.txt")).unwrap();
+    assert!(index.starts_with("file_bundle.001.txt\tbig.txt\nfile_bundle.002.txt\tbig.txt (continued)\n"), "{}", index);
+    let mut parts: Vec<String> = fs::read_dir(&root)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name.starts_with("file_bundle.0"))
+        .collect();
+    parts.sort();
+    assert!(parts.len() > 2, "{:?}", parts);
+    for part in &parts {
+        assert!(fs::metadata(root.join(part)).unwrap().len() <= 1500);
+    }
+
+    let mut args = vec!["unbundle", "-f", "---"];
+    args.extend(parts.iter().map(String::as_str));
+    args.extend(["-o", "out"]);
+    fbundle(&root, &args);
+    assert_eq!(fs::read_to_string(root.join("out/big.txt")).unwrap(), big);
+    assert_eq!(fs::read_to_string(root.join("out/small.txt")).unwrap(), "small\n");
+    assert_eq!(fs::read_to_string(root.join("out/last.txt")).unwrap(), "no newline");
+    assert!(!root.join("out/big.txt (continued)").exists());
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn headings_inside_markdown_code_blocks_are_not_headers() {
+    let root = scratch_dir("unbundle-markdown-headings");
+    fs::create_dir_all(root.join("src")).unwrap();
+    let readme = "# Tool\n\n## Usage\n\n```sh\ntool --help\n```\n";
+    fs::write(root.join("src/README.md"), readme).unwrap();
+    fs::write(root.join("src/main.rs"), "fn main() {}\n").unwrap();
+
+    for footer in [&[][..], &["--footer-template", "{sep} end of {path}"][..]] {
+        let made = fbundle(&root, &[&["-s", "src", "-g", "*", "--no-cache", "--format", "markdown"][..], footer].concat());
+        assert!(!String::from_utf8_lossy(&made.stderr).contains("Warning"), "{}", String::from_utf8_lossy(&made.stderr));
+
+        let out = format!("out{}", footer.len());
+        fbundle(&root, &[&["unbundle", "file_bundle.md", "-o", &out][..], footer].concat());
+        assert_eq!(fs::read_to_string(root.join(&out).join("README.md")).unwrap(), readme);
+        assert_eq!(fs::read_to_string(root.join(&out).join("main.rs")).unwrap(), "fn main() {}\n");
+
+        let applied = fbundle(&root, &[&["apply", "file_bundle.md", "-s", "src"][..], footer].concat());
+        assert!(String::from_utf8_lossy(&applied.stdout).contains("Applied 0 changed files to src (2 unchanged)"));
+    }
+
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,56 @@
+use std::fs;
+
+mod common;
+use common::{run, scratch_dir};
+
+#[test]
+fn a_manifest_of_a_walked_selection_verifies() {
+    let root = scratch_dir("verify-walked");
+    fs::create_dir_all(root.join("src")).unwrap();
+    fs::write(root.join("src/a.rs"), "a\n").unwrap();
+
+    let made = run(&root, &["-s", "src", "-f", "---", "-g", "*", "--manifest", "sidecar", "--no-cache"]);
+    assert!(made.status.success(), "{}", String::from_utf8_lossy(&made.stderr));
+    let verified = run(&root, &["verify", "file_bundle.txt", "-s", "src"]);
+    assert!(verified.status.success(), "{}", String::from_utf8_lossy(&verified.stderr));
+    assert!(String::from_utf8_lossy(&verified.stdout).contains("file_bundle.txt matches src (1 files)"));
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn added_removed_modified_and_mode_changes_are_listed() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let root = scratch_dir("verify-changes");
+    let src = root.join("src");
+    fs::create_dir_all(&src).unwrap();
+    for name in ["a.rs", "b.rs", "c.rs", "same.rs"] {
+        fs::write(src.join(name), name).unwrap();
+        fs::set_permissions(src.join(name), fs::Permissions::from_mode(0o644)).unwrap();
+    }
+    let made = run(&root, &["-s", "src", "-f", "---", "-g", "*.rs", "--manifest", "sidecar", "--no-cache"]);
+    assert!(made.status.success(), "{}", String::from_utf8_lossy(&made.stderr));
+
+    fs::write(src.join("a.rs"), "a.rs, edited").unwrap();
+    fs::set_permissions(src.join("b.rs"), fs::Permissions::from_mode(0o755)).unwrap();
+    fs::remove_file(src.join("c.rs")).unwrap();
+    fs::write(src.join("d.rs"), "d.rs").unwrap();
+    // Not matched by the recorded globs, so not added.
+    fs::write(src.join("notes.txt"), "notes").unwrap();
+
+    let verified = run(&root, &["verify", "file_bundle.txt", "-s", "src", "-v"]);
+    assert_eq!(verified.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&verified.stdout),
+        "modified  a.rs\n\
+         mode      b.rs (0644 -> 0755)\n\
+         removed   c.rs\n\
+         added     d.rs\n\
+         ok        same.rs\n\
+         4 differences between file_bundle.txt and src\n"
+    );
+
+    fs::remove_dir_all(&root).unwrap();
+}